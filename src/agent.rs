@@ -1,3 +1,11 @@
+pub mod agent_tool;
 pub mod base;
+pub mod group_chat;
+pub mod handoff;
+pub mod plan_execute_agent;
 pub mod react_agent;
-pub mod tool_agent;
\ No newline at end of file
+pub mod reflective_agent;
+pub mod registry;
+pub mod termination;
+pub mod tool_agent;
+pub mod typed;
\ No newline at end of file
@@ -0,0 +1,216 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{fmt, sync::{Mutex, OnceLock}};
+use tokio::runtime::Runtime;
+use crate::{agent::base::BaseAgent, model::schema::Usage, prompt::agent::DELEGATED_TASK_PROMPT, tool::base::Tool};
+
+/// What a sub-agent remembers between calls
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SubAgentMemory {
+    /// The history is cleared before every call
+    #[default]
+    Isolated,
+    /// The history is kept, later calls see the earlier tasks and answers
+    Persistent,
+}
+
+// shut down without waiting, an `AgentTool` may be dropped inside async code
+#[derive(Default)]
+struct SubRuntime(OnceLock<Runtime>);
+
+impl Drop for SubRuntime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DelegatedTask {
+    task: String,
+    #[serde(default)]
+    context: Option<String>,
+}
+
+/// Wraps an agent as a `Tool` with the arguments `{"task": string, "context"?: string}`,
+/// so a supervisor agent can delegate to it through its `ToolManager`.
+/// The usage of every sub-run is handed to the calling agent through `Tool::take_usage`.
+pub struct AgentTool<A: BaseAgent> {
+    schema: Value,
+    agent: Mutex<A>,
+    memory: SubAgentMemory,
+    usage: Mutex<Usage>,
+    // kept across calls so connections pooled by the sub-agent's model stay usable
+    runtime: SubRuntime,
+}
+
+impl <A: BaseAgent> fmt::Debug for AgentTool<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentTool").field("name", &self.schema["name"]).field("memory", &self.memory).finish_non_exhaustive()
+    }
+}
+
+impl <A: BaseAgent> AgentTool<A> {
+    pub fn new(name: &str, description: &str, agent: A) -> Self {
+        AgentTool {
+            schema: json!({
+                "name": name,
+                "description": description,
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "task": { "type": "string", "description": "The task for the agent, self-contained." },
+                        "context": { "type": "string", "description": "Background the agent needs, e.g. results found so far." }
+                    },
+                    "required": ["task"]
+                }
+            }),
+            agent: Mutex::new(agent),
+            memory: SubAgentMemory::default(),
+            usage: Mutex::new(Usage::default()),
+            runtime: SubRuntime::default(),
+        }
+    }
+
+    pub fn with_memory(mut self, memory: SubAgentMemory) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Give the wrapped agent back, e.g. to inspect its history
+    pub fn into_inner(self) -> A {
+        self.agent.into_inner().unwrap()
+    }
+}
+
+impl <A: BaseAgent + Send> AgentTool<A> {
+    fn runtime(&self) -> std::io::Result<&Runtime> {
+        if let Some(runtime) = self.runtime.0.get() {
+            return Ok(runtime);
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(1).thread_name("agent-tool").enable_all().build()?;
+        Ok(self.runtime.0.get_or_init(|| runtime))
+    }
+
+    /// Runs the agent to completion, a failure to do so is returned as the tool output
+    fn run_agent(&self, agent: &mut A, prompt: &str) -> String {
+        if self.memory == SubAgentMemory::Isolated {
+            agent.clear_history();
+        }
+        let runtime = match self.runtime() {
+            Ok(runtime) => runtime,
+            Err(e) => return format!("Failed to start {}: {}", self.name(), e),
+        };
+        // tools are executed synchronously, the sub-run blocks on the tool's own runtime from a scoped thread
+        // so it works under both the current-thread and the multi-thread runtime of the caller
+        let span = tracing::info_span!("invoke_sub_agent", gen_ai.agent.name = self.name());
+        let result = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _entered = span.enter();
+                runtime.block_on(agent.run(prompt))
+            }).join()
+        });
+        result.unwrap_or_else(|_| format!("{} failed without an answer", self.name()))
+    }
+}
+
+impl <A: BaseAgent + Send> Tool for AgentTool<A> {
+    fn load(&self) -> &Value {
+        &self.schema
+    }
+
+    fn init(&mut self) {}
+
+    fn execute(&self, input: &str) -> String {
+        let delegated = match serde_json::from_str::<DelegatedTask>(input) {
+            Ok(delegated) => delegated,
+            Err(e) => return format!("Invalid arguments for {}: {}", self.name(), e),
+        };
+        let prompt = match delegated.context.as_deref() {
+            Some(context) if !context.trim().is_empty() => DELEGATED_TASK_PROMPT.replace("{context}", context).replace("{task}", &delegated.task),
+            _ => delegated.task,
+        };
+        let mut agent = self.agent.lock().unwrap();
+        let answer = self.run_agent(&mut agent, &prompt);
+        if let Some(usage) = agent.run_usage() {
+            self.usage.lock().unwrap().accumulate(&usage);
+        }
+        answer
+    }
+
+    fn take_usage(&self) -> Option<Usage> {
+        Some(std::mem::take(&mut *self.usage.lock().unwrap()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::react_agent::ReactAgent, memory::sliding_window::SlidingWindowMemory,
+                model::schema::{LLMResponse, Message, Role}, test_support::*, tool::manager::ToolManager};
+
+    // the specialist answers with the number of messages it sees
+    fn specialist(config: &crate::config::config::Config) -> ReactAgent<SlidingWindowMemory> {
+        let counting = FnModel(|history: &[&Message]| LLMResponse { usage: Some(usage(10)), ..answer(&format!("seen {}", history.len())) });
+        react_agent(config, "You are a specialist.", SlidingWindowMemory::new(50, MODEL, 100000), counting)
+    }
+
+    #[test]
+    fn test_agent_tool_memory_modes() {
+        let config = offline_config();
+        let isolated = AgentTool::new("specialist", "Counts.", specialist(&config));
+        let persistent = AgentTool::new("specialist", "Counts.", specialist(&config)).with_memory(SubAgentMemory::Persistent);
+        for _ in 0..2 {
+            isolated.execute(r#"{"task": "count"}"#);
+            persistent.execute(r#"{"task": "count"}"#);
+        }
+        assert_eq!(isolated.execute(r#"{"task": "count"}"#), "seen 1");
+        assert_eq!(persistent.execute(r#"{"task": "count"}"#), "seen 5");
+        assert_eq!(isolated.take_usage().unwrap().total_tokens, 30);
+        assert_eq!(isolated.take_usage().unwrap().total_tokens, 0);
+        assert!(isolated.execute("not json").starts_with("Invalid arguments for specialist"));
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_rolls_up_sub_agent_usage() {
+        let config = offline_config();
+        let tool = AgentTool::new("specialist", "Counts.", specialist(&config));
+        // the supervisor delegates once, then answers with the tool result
+        let supervisor_model = FnModel(|history: &[&Message]| {
+            let last = history.last().unwrap();
+            let response = if last.role == Role::TOOL {
+                answer(&last.content)
+            } else {
+                tool_calls(vec![tool_call("call_0", "specialist", r#"{"task": "count", "context": "none"}"#)])
+            };
+            LLMResponse { usage: Some(usage(1)), ..response }
+        });
+        let mut supervisor = ReactAgent::new(
+            &config,
+            MODEL,
+            "You are a supervisor.",
+            3,
+            ToolManager::new(vec![Box::new(tool)]),
+            SlidingWindowMemory::new(50, MODEL, 100000),
+            vec!["specialist".to_string()]
+        ).map_model(|_| Box::new(supervisor_model));
+
+        let answer = supervisor.run("Delegate the counting.").await;
+        assert_eq!(answer, "seen 1");
+        // two supervisor calls and one specialist call
+        assert_eq!(supervisor.last_usage().total_tokens, 12);
+    }
+
+    #[test]
+    fn test_failed_sub_run_is_the_tool_output() {
+        let config = offline_config();
+        let broken = react_agent(&config, "You are a specialist.", SlidingWindowMemory::new(50, MODEL, 100000),
+                                 FnModel(|_: &[&Message]| -> LLMResponse { panic!("model crashed") }));
+        let tool = AgentTool::new("specialist", "Crashes.", broken);
+        assert_eq!(tool.execute(r#"{"task": "count"}"#), "specialist failed without an answer");
+        // the tool stays usable
+        assert_eq!(tool.execute(r#"{"task": "count"}"#), "specialist failed without an answer");
+    }
+}
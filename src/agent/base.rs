@@ -1,11 +1,41 @@
-use crate::model::schema::*;
+use crate::{memory::base::MessageIter, model::schema::*};
 use async_trait::async_trait;
 
 #[async_trait]
 pub trait BaseAgent {
-    fn get_history(&self) -> impl Iterator<Item = &Message>;
+    fn get_history(&self) -> MessageIter<'_>;
     fn clear_history(&mut self);
-    fn build_messages(&self) -> impl Iterator<Item = &Message>;
+    fn build_messages(&self) -> MessageIter<'_>;
     async fn add_message(&mut self, message: Message);
     async fn run(&mut self, user_prompt: &str) -> String;
-}
\ No newline at end of file
+    // usage of the latest run, `None` if the agent does not track it
+    fn run_usage(&self) -> Option<Usage> { None }
+}
+
+/// A boxed agent, e.g. one held by `AgentRegistry`, is an agent
+#[async_trait]
+impl <A: BaseAgent + Send + ?Sized> BaseAgent for Box<A> {
+    fn get_history(&self) -> MessageIter<'_> {
+        (**self).get_history()
+    }
+
+    fn clear_history(&mut self) {
+        (**self).clear_history();
+    }
+
+    fn build_messages(&self) -> MessageIter<'_> {
+        (**self).build_messages()
+    }
+
+    async fn add_message(&mut self, message: Message) {
+        (**self).add_message(message).await;
+    }
+
+    async fn run(&mut self, user_prompt: &str) -> String {
+        (**self).run(user_prompt).await
+    }
+
+    fn run_usage(&self) -> Option<Usage> {
+        (**self).run_usage()
+    }
+}
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use std::sync::Arc;
+use async_trait::async_trait;
+use tracing::Instrument;
+use crate::{agent::base::BaseAgent, config::config::Config,
+            model::{cache::CachedModel, litellm_model::LitellmModel, metered::MeteredModel, options::GenerationOptions, schema::{Message, Usage}, structured::StructuredOutput},
+            prompt::group_chat::*};
+
+/// Speaker of the task that starts a group chat
+pub const USER_SPEAKER: &str = "user";
+
+/// A message of the shared transcript, attributed to the agent that wrote it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub speaker: String,
+    pub content: String,
+}
+
+/// Any agent can take part in a group chat, this keeps agents of different types in one list
+#[async_trait]
+pub trait Participant: Send {
+    async fn respond(&mut self, prompt: &str) -> String;
+    fn usage(&self) -> Option<Usage>;
+}
+
+#[async_trait]
+impl <A: BaseAgent + Send> Participant for A {
+    async fn respond(&mut self, prompt: &str) -> String {
+        self.run(prompt).await
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.run_usage()
+    }
+}
+
+pub type SelectSpeaker = Arc<dyn Fn(&[ChatMessage], &[String]) -> String + Send + Sync>;
+pub type ChatTermination = Arc<dyn Fn(&[ChatMessage]) -> bool + Send + Sync>;
+
+/// Decides who speaks next
+pub enum SpeakerSelector {
+    /// Participants speak in the order they were added
+    RoundRobin,
+    /// A model picks the next speaker from the transcript and the participant descriptions
+    Model(MeteredModel),
+    /// `f(transcript, names)` returns the name of the next speaker
+    Custom(SelectSpeaker),
+}
+
+impl SpeakerSelector {
+    pub fn model(config: &Config, model_name: &str) -> Self {
+        let model_config = config.models.get(model_name).unwrap_or_else(|| panic!("Model {} not found in config", model_name));
+        let model = CachedModel::wrap_if_enabled(Box::new(LitellmModel::new(model_name, model_config, SPEAKER_SELECTOR_SYSTEM_PROMPT)), model_config);
+        SpeakerSelector::Model(MeteredModel::new(model))
+    }
+
+    pub fn custom<F: Fn(&[ChatMessage], &[String]) -> String + Send + Sync + 'static>(f: F) -> Self {
+        SpeakerSelector::Custom(Arc::new(f))
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct SpeakerChoice {
+    /// Name of the participant who speaks next
+    speaker: String,
+}
+
+struct Member {
+    name: String,
+    description: String,
+    agent: Box<dyn Participant>,
+    // transcript index up to which the member has seen the conversation
+    seen: usize,
+}
+
+/// The result of `GroupChat::run`
+#[derive(Debug, Clone)]
+pub struct GroupChatOutcome {
+    pub transcript: Vec<ChatMessage>,
+    pub rounds: usize,
+    /// The termination condition ended the chat, otherwise `max_rounds` was reached
+    pub terminated: bool,
+    pub usage: Usage,
+}
+
+/// Several named agents share a transcript, every round the selector picks one of them to speak.
+/// Each agent only receives the messages it has not seen yet, its own memory keeps the rest.
+pub struct GroupChat {
+    members: Vec<Member>,
+    selector: SpeakerSelector,
+    termination: Option<ChatTermination>,
+    max_rounds: usize,
+    transcript: Vec<ChatMessage>,
+}
+
+impl GroupChat {
+    pub fn new(selector: SpeakerSelector, max_rounds: usize) -> Self {
+        GroupChat { members: Vec::new(), selector, termination: None, max_rounds, transcript: Vec::new() }
+    }
+
+    /// Add a participant, `name` must be unique, `description` is what the model selector sees
+    pub fn with_agent<A: Participant + 'static>(mut self, name: &str, description: &str, agent: A) -> Self {
+        assert!(self.members.iter().all(|member| member.name != name), "Agent {} is already in the group chat", name);
+        self.members.push(Member { name: name.to_string(), description: description.to_string(), agent: Box::new(agent), seen: 0 });
+        self
+    }
+
+    /// `condition(transcript)` is checked after every message and ends the chat when true
+    pub fn with_termination<F: Fn(&[ChatMessage]) -> bool + Send + Sync + 'static>(mut self, condition: F) -> Self {
+        self.termination = Some(Arc::new(condition));
+        self
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.members.iter().map(|member| member.name.clone()).collect()
+    }
+
+    pub fn transcript(&self) -> &[ChatMessage] {
+        &self.transcript
+    }
+
+    fn format_transcript(messages: &[ChatMessage]) -> String {
+        messages.iter().map(|m| format!("[{}]: {}", m.speaker, m.content)).collect::<Vec<String>>().join("\n\n")
+    }
+
+    fn next_round_robin(&self) -> usize {
+        let last = self.transcript.last().and_then(|m| self.members.iter().position(|member| member.name == m.speaker));
+        last.map_or(0, |i| (i + 1) % self.members.len())
+    }
+
+    async fn select_speaker(&self) -> usize {
+        let names = self.names();
+        let chosen = match &self.selector {
+            SpeakerSelector::RoundRobin => return self.next_round_robin(),
+            SpeakerSelector::Custom(select) => select(&self.transcript, &names),
+            SpeakerSelector::Model(model) => {
+                let participants = self.members.iter().map(|m| format!("- {}: {}", m.name, m.description)).collect::<Vec<String>>().join("\n");
+                let last = self.transcript.last().map_or(USER_SPEAKER, |m| m.speaker.as_str());
+                let prompt = Message::user(&SELECT_SPEAKER_PROMPT.replace("{participants}", &participants)
+                                                               .replace("{transcript}", &Self::format_transcript(&self.transcript))
+                                                               .replace("{last}", last));
+                match model.call_structured::<SpeakerChoice>(vec![&prompt], &GenerationOptions::default()).await {
+                    Ok(choice) => choice.speaker,
+                    Err(e) => {
+                        tracing::error!("Failed to select the next speaker: {}", e);
+                        String::new()
+                    }
+                }
+            }
+        };
+        names.iter().position(|name| name.trim() == chosen.trim()).unwrap_or_else(|| {
+            tracing::error!("Unknown speaker {:?}, falling back to round robin", chosen);
+            self.next_round_robin()
+        })
+    }
+
+    /// Start a new conversation on `task`, the agents keep their own memories between runs
+    pub async fn run(&mut self, task: &str) -> GroupChatOutcome {
+        assert!(!self.members.is_empty(), "Group chat has no agents");
+        self.transcript = vec![ChatMessage { speaker: USER_SPEAKER.to_string(), content: task.to_string() }];
+        for member in self.members.iter_mut() {
+            member.seen = 0;
+        }
+        if let SpeakerSelector::Model(model) = &self.selector {
+            model.take_usage();
+        }
+        let mut usage = Usage::default();
+        let mut rounds = 0;
+        let mut terminated = false;
+        while rounds < self.max_rounds {
+            rounds += 1;
+            let index = self.select_speaker().await;
+            let names = self.names();
+            let member = &mut self.members[index];
+            let others = names.iter().filter(|name| **name != member.name).cloned().collect::<Vec<String>>().join(", ");
+            let prompt = GROUP_CHAT_TURN_PROMPT.replace("{name}", &member.name)
+                                               .replace("{participants}", &others)
+                                               .replace("{transcript}", &Self::format_transcript(&self.transcript[member.seen..]));
+            let turn_span = tracing::info_span!("group_chat_turn", r_agent.round = rounds, gen_ai.agent.name = member.name.as_str());
+            let content = member.agent.respond(&prompt).instrument(turn_span).await;
+            if let Some(turn_usage) = member.agent.usage() {
+                usage.accumulate(&turn_usage);
+            }
+            tracing::debug!("[{}]: {}", member.name, content);
+            self.transcript.push(ChatMessage { speaker: member.name.clone(), content });
+            member.seen = self.transcript.len();
+            if self.termination.as_ref().is_some_and(|condition| condition(&self.transcript)) {
+                terminated = true;
+                break;
+            }
+        }
+        if let SpeakerSelector::Model(model) = &self.selector {
+            usage.accumulate(&model.take_usage());
+        }
+        GroupChatOutcome { transcript: self.transcript.clone(), rounds, terminated, usage }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::react_agent::ReactAgent, memory::sliding_window::SlidingWindowMemory, test_support::*};
+
+    // replies with a fixed line and the number of transcript lines it was given
+    fn agent(config: &Config, reply: &'static str) -> ReactAgent<SlidingWindowMemory> {
+        let model = FnModel(move |history: &[&Message]| {
+            answer(&format!("{} ({} new)", reply, history.last().unwrap().content.matches("]: ").count()))
+        });
+        react_agent(config, "You are a chat member.", SlidingWindowMemory::new(50, MODEL, 100000), model)
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_until_max_rounds() {
+        let config = offline_config();
+        let mut chat = GroupChat::new(SpeakerSelector::RoundRobin, 4)
+                        .with_agent("writer", "Writes drafts.", agent(&config, "draft"))
+                        .with_agent("critic", "Reviews drafts.", agent(&config, "review"));
+        let outcome = chat.run("Write a haiku about dreams.").await;
+        let speakers: Vec<&str> = outcome.transcript.iter().map(|m| m.speaker.as_str()).collect();
+        assert_eq!(speakers, vec![USER_SPEAKER, "writer", "critic", "writer", "critic"]);
+        assert!(!outcome.terminated);
+        // every agent only gets the messages since its last turn
+        assert_eq!(outcome.transcript[2].content, "review (2 new)");
+        assert_eq!(outcome.transcript[3].content, "draft (1 new)");
+    }
+
+    #[tokio::test]
+    async fn test_model_and_custom_selectors() {
+        let config = offline_config();
+        let selector = SpeakerSelector::Model(MeteredModel::new(Box::new(ScriptedModel::new([
+            r#"{"speaker": "critic"}"#,
+            r#"{"speaker": "nobody"}"#,
+        ]))));
+        let mut chat = GroupChat::new(selector, 5)
+                        .with_agent("writer", "Writes drafts.", agent(&config, "draft"))
+                        .with_agent("critic", "Reviews drafts.", agent(&config, "APPROVED"))
+                        .with_termination(|transcript| transcript.len() > 2);
+        let outcome = chat.run("Write a haiku about dreams.").await;
+        let speakers: Vec<&str> = outcome.transcript.iter().map(|m| m.speaker.as_str()).collect();
+        // the unknown speaker falls back to the one after the critic
+        assert_eq!(speakers, vec![USER_SPEAKER, "critic", "writer"]);
+        assert!(outcome.terminated);
+        assert_eq!(outcome.rounds, 2);
+
+        let mut chat = GroupChat::new(SpeakerSelector::custom(|_, names| names[1].clone()), 2)
+                        .with_agent("writer", "Writes drafts.", agent(&config, "draft"))
+                        .with_agent("critic", "Reviews drafts.", agent(&config, "review"));
+        let outcome = chat.run("Write a haiku about dreams.").await;
+        assert!(outcome.transcript[1..].iter().all(|m| m.speaker == "critic"));
+    }
+}
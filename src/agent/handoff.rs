@@ -0,0 +1,250 @@
+use serde_json::{json, Value};
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::{agent::{base::BaseAgent, group_chat::Participant, react_agent::ReactAgent},
+            memory::base::BaseMemory,
+            model::schema::{Message, Role, Usage},
+            prompt::agent::*};
+
+pub type HistoryFilter = Arc<dyn Fn(&Message) -> bool + Send + Sync>;
+
+pub(crate) fn handoff_tool_schema(agent: &str, description: &str) -> Value {
+    json!({
+        "name": format!("{}{}", HANDOFF_TOOL_PREFIX, agent),
+        "description": HANDOFF_TOOL_DESCRIPTION.replace("{agent}", agent).replace("{description}", description),
+        "parameters": { "type": "object", "properties": {} }
+    })
+}
+
+/// The default history filter, keeps user messages and answers but drops tool calls and results,
+/// which the next agent could not match to its own tools
+pub fn conversation_only(message: &Message) -> bool {
+    match message.role {
+        Role::USER => true,
+        Role::ASSISTANT => message.tool_calls.is_none(),
+        _ => false,
+    }
+}
+
+/// An agent that can hand the conversation to another one
+#[async_trait]
+pub trait HandoffAgent: Participant {
+    /// Offer a transfer tool for every `(name, description)`
+    fn set_handoffs(&mut self, targets: &[(String, String)]);
+    /// The agent the latest response transferred to
+    fn take_handoff(&mut self) -> Option<String>;
+    fn history(&self) -> Vec<Message>;
+    /// Add messages carried over from the previous agent
+    async fn receive(&mut self, messages: Vec<Message>);
+}
+
+#[async_trait]
+impl <M: BaseMemory + Send> HandoffAgent for ReactAgent<M> {
+    fn set_handoffs(&mut self, targets: &[(String, String)]) {
+        ReactAgent::set_handoffs(self, targets);
+    }
+
+    fn take_handoff(&mut self) -> Option<String> {
+        ReactAgent::take_handoff(self)
+    }
+
+    fn history(&self) -> Vec<Message> {
+        self.get_history().cloned().collect()
+    }
+
+    async fn receive(&mut self, messages: Vec<Message>) {
+        for message in messages {
+            self.add_message(message).await;
+        }
+    }
+}
+
+struct Specialist {
+    name: String,
+    description: String,
+    agent: Box<dyn HandoffAgent>,
+    // history length when the agent took over, later messages are carried over on the next handoff
+    start: usize,
+}
+
+/// Routes a user session between agents, every agent can transfer it to any other through `transfer_to_<name>`.
+/// The agent that receives the conversation gets the filtered messages of the previous agent and stays active
+/// for the following user turns.
+pub struct HandoffRouter {
+    agents: Vec<Specialist>,
+    active: usize,
+    filter: HistoryFilter,
+    // transfers within one user turn, guards against agents passing the conversation back and forth
+    max_handoffs: usize,
+    route: Vec<String>,
+    last_usage: Usage,
+}
+
+impl HandoffRouter {
+    pub fn new(max_handoffs: usize) -> Self {
+        HandoffRouter {
+            agents: Vec::new(),
+            active: 0,
+            filter: Arc::new(conversation_only),
+            max_handoffs,
+            route: Vec::new(),
+            last_usage: Usage::default(),
+        }
+    }
+
+    /// Add an agent, the first one added starts the session. `name` becomes part of a tool name.
+    pub fn with_agent<A: HandoffAgent + 'static>(mut self, name: &str, description: &str, agent: A) -> Self {
+        assert!(!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'), "Invalid agent name {}", name);
+        assert!(self.agents.iter().all(|specialist| specialist.name != name), "Agent {} is already registered", name);
+        self.agents.push(Specialist { name: name.to_string(), description: description.to_string(), agent: Box::new(agent), start: 0 });
+        for i in 0..self.agents.len() {
+            let others = self.targets(i);
+            self.agents[i].agent.set_handoffs(&others);
+        }
+        self
+    }
+
+    // every agent but the one at `index`
+    fn targets(&self, index: usize) -> Vec<(String, String)> {
+        self.agents.iter().enumerate().filter(|(i, _)| *i != index).map(|(_, s)| (s.name.clone(), s.description.clone())).collect()
+    }
+
+    /// Which messages are carried over to the next agent, `conversation_only` by default
+    pub fn with_history_filter<F: Fn(&Message) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.filter = Arc::new(filter);
+        self
+    }
+
+    /// The agent that answers the next user turn
+    pub fn active(&self) -> &str {
+        &self.agents[self.active].name
+    }
+
+    /// The agents that handled the latest turn in order
+    pub fn route(&self) -> &[String] {
+        &self.route
+    }
+
+    /// Total usage of all agents during the latest turn
+    pub fn last_usage(&self) -> &Usage {
+        &self.last_usage
+    }
+
+    pub fn history(&self, name: &str) -> Option<Vec<Message>> {
+        self.agents.iter().find(|s| s.name == name).map(|s| s.agent.history())
+    }
+
+    /// One user turn, handled by the active agent and whoever it transfers to.
+    /// An agent that tries to transfer beyond `max_handoffs` is asked again without the transfer tools.
+    pub async fn run(&mut self, user_prompt: &str) -> String {
+        assert!(!self.agents.is_empty(), "No agents registered");
+        self.route = vec![self.agents[self.active].name.clone()];
+        let mut usage = Usage::default();
+        let mut prompt = user_prompt.to_string();
+        let mut handoffs = 0;
+        let answer = loop {
+            let current = &mut self.agents[self.active];
+            let answer = current.agent.respond(&prompt).await;
+            if let Some(turn_usage) = current.agent.usage() {
+                usage.accumulate(&turn_usage);
+            }
+            let Some(target) = current.agent.take_handoff() else { break answer };
+            let next = self.agents.iter().position(|s| s.name == target);
+            let Some(next) = next.filter(|_| handoffs < self.max_handoffs) else {
+                tracing::error!("Cannot hand off to {} after {} of {} handoffs, {} answers itself", target, handoffs, self.max_handoffs, self.active());
+                break self.answer_without_handoffs(user_prompt, &mut usage).await;
+            };
+            handoffs += 1;
+
+            let current = &self.agents[self.active];
+            let history = current.agent.history();
+            let carried: Vec<Message> = history[current.start.min(history.len())..].iter().filter(|m| (self.filter)(m)).cloned().collect();
+            tracing::debug!("Handing off from {} to {} with {} messages", current.name, target, carried.len());
+            prompt = HANDOFF_PROMPT.replace("{from}", &current.name).replace("{prompt}", user_prompt);
+            let next_agent = &mut self.agents[next];
+            next_agent.agent.receive(carried).await;
+            next_agent.start = next_agent.agent.history().len();
+            self.active = next;
+            self.route.push(target);
+        };
+        self.last_usage = usage;
+        answer
+    }
+
+    async fn answer_without_handoffs(&mut self, user_prompt: &str, usage: &mut Usage) -> String {
+        let targets = self.targets(self.active);
+        let current = &mut self.agents[self.active];
+        current.agent.set_handoffs(&[]);
+        let answer = current.agent.respond(&HANDOFF_LIMIT_PROMPT.replace("{prompt}", user_prompt)).await;
+        if let Some(turn_usage) = current.agent.usage() {
+            usage.accumulate(&turn_usage);
+        }
+        current.agent.set_handoffs(&targets);
+        answer
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::config::Config, memory::sliding_window::SlidingWindowMemory, model::base::BaseModel, test_support::*};
+
+    fn agent<B: BaseModel + 'static>(config: &Config, model: B) -> ReactAgent<SlidingWindowMemory> {
+        react_agent(config, "You are a support agent.", SlidingWindowMemory::new(50, MODEL, 100000), model)
+    }
+
+    #[tokio::test]
+    async fn test_triage_hands_off_to_billing() {
+        let config = offline_config();
+        // routes anything mentioning an invoice to billing
+        let triage = FnModel(|history: &[&Message]| {
+            let last = history.last().unwrap();
+            if last.role == Role::USER && last.content.contains("invoice") {
+                return tool_calls(vec![tool_call("call_0", "transfer_to_billing", "{}")]);
+            }
+            answer("Hello!")
+        });
+        // answers with the number of user messages it knows about
+        let billing = FnModel(|history: &[&Message]| answer(&format!("billing saw {}", history.iter().filter(|m| m.role == Role::USER).count())));
+        let mut router = HandoffRouter::new(2)
+                            .with_agent("triage", "Greets users and routes requests.", agent(&config, triage))
+                            .with_agent("billing", "Invoices, refunds and payments.", agent(&config, billing));
+
+        assert_eq!(router.run("Hi").await, "Hello!");
+        assert_eq!(router.active(), "triage");
+
+        // the user's question is carried over and the billing agent is prompted once more
+        assert_eq!(router.run("Where is my invoice?").await, "billing saw 3");
+        assert_eq!(router.route(), ["triage", "billing"]);
+        assert_eq!(router.active(), "billing");
+        let billing = router.history("billing").unwrap();
+        assert!(billing.iter().all(|m| m.role != Role::TOOL && m.tool_calls.is_none()));
+        assert!(billing.iter().any(|m| m.content == "Where is my invoice?"));
+
+        // the session stays with billing
+        assert_eq!(router.run("And a refund?").await, "billing saw 4");
+        assert_eq!(router.route(), ["billing"]);
+    }
+
+    #[tokio::test]
+    async fn test_max_handoffs_answers_without_transfer() {
+        let config = offline_config();
+        // always transfers, looking something up in the same response, unless it may not
+        let triage = FnModel(|history: &[&Message]| {
+            if history.last().unwrap().content.contains("yourself") {
+                return answer("triage answered");
+            }
+            tool_calls(vec![tool_call("call_0", "transfer_to_billing", "{}"), tool_call("call_1", "lookup", "{}")])
+        });
+        let mut router = HandoffRouter::new(0)
+                            .with_agent("triage", "Greets users and routes requests.", agent(&config, triage))
+                            .with_agent("billing", "Invoices, refunds and payments.", agent(&config, echo_model()));
+
+        assert_eq!(router.run("Where is my invoice?").await, "triage answered");
+        assert_eq!(router.route(), ["triage"]);
+        let triage = router.history("triage").unwrap();
+        let results: Vec<&str> = triage.iter().filter(|m| m.role == Role::TOOL).map(|m| m.content.as_str()).collect();
+        assert_eq!(results, vec![HANDOFF_RESULT.replace("{agent}", "billing").as_str(), SKIPPED_TOOL_RESULT]);
+    }
+}
@@ -0,0 +1,296 @@
+use serde::Deserialize;
+use schemars::JsonSchema;
+use std::{path::PathBuf, time::Instant};
+use async_trait::async_trait;
+use tracing::Instrument;
+use crate::{agent::{base::BaseAgent, react_agent::ReactAgent, tool_agent::ToolAgent},
+            config::config::Config, memory::base::{BaseMemory, MessageIter},
+            model::{base::BaseModel, cache::CachedModel, litellm_model::LitellmModel, metered::MeteredModel, options::GenerationOptions, schema::{Message, Usage}, structured::StructuredOutput},
+            prompt::plan::*,
+            trajectory::{logger::TrajectoryLogger, schema::{PlanStep, StepStatus, TrajectoryEvent}}};
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct PlanDraft {
+    /// The steps in execution order
+    steps: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct PlanRevision {
+    /// The steps still to execute, in order, empty when the task is complete
+    remaining_steps: Vec<String>,
+    /// The answer to the task, only when it is complete
+    final_answer: Option<String>,
+}
+
+/// Plan-and-execute agent
+/// A planner model writes a plan, a `ReactAgent` executes it step by step, and after every step the planner
+/// revises the remaining steps with the result. The executor's memory is the memory of this agent.
+pub struct PlanExecuteAgent<M: BaseMemory> {
+    planner: MeteredModel,
+    executor: ReactAgent<M>,
+    // executed steps per run, revisions may keep adding steps
+    max_steps: usize,
+    plan: Vec<PlanStep>,
+    trajectory_dir: Option<PathBuf>,
+    last_usage: Usage,
+}
+
+impl <M: BaseMemory> PlanExecuteAgent<M> {
+    pub fn new(config: &Config, planner_model: &str, executor: ReactAgent<M>, max_steps: usize) -> Self {
+        let model_config = config.models.get(planner_model).unwrap_or_else(|| panic!("Model {} not found in config", planner_model));
+        let planner = CachedModel::wrap_if_enabled(Box::new(LitellmModel::new(planner_model, model_config, PLANNER_SYSTEM_PROMPT)), model_config);
+        PlanExecuteAgent {
+            planner: MeteredModel::new(planner),
+            executor,
+            max_steps,
+            plan: Vec::new(),
+            trajectory_dir: None,
+            last_usage: Usage::default(),
+        }
+    }
+
+    /// Like `ReactAgent::map_model`, for the planner that writes and revises the plan
+    pub fn map_planner<F: FnOnce(Box<dyn BaseModel>) -> Box<dyn BaseModel>>(mut self, f: F) -> Self {
+        self.planner = self.planner.map_inner(f);
+        self
+    }
+
+    /// Write a JSONL trajectory of every run into `dir`, the executor logs its own runs if configured
+    pub fn with_trajectory_dir(mut self, dir: &str) -> Self {
+        self.trajectory_dir = Some(PathBuf::from(dir));
+        self
+    }
+
+    /// The plan of the latest run with the status and result of every step
+    pub fn plan(&self) -> &[PlanStep] {
+        &self.plan
+    }
+
+    pub fn executor(&self) -> &ReactAgent<M> {
+        &self.executor
+    }
+
+    /// Total usage of the latest run, planner and executor
+    pub fn last_usage(&self) -> &Usage {
+        &self.last_usage
+    }
+
+    fn format_completed(&self) -> String {
+        let completed: Vec<String> = self.plan.iter().enumerate()
+            .filter(|(_, step)| step.status != StepStatus::Pending)
+            .map(|(i, step)| format!("{}. {} [{:?}]\n   Result: {}", i + 1, step.description, step.status, step.result.as_deref().unwrap_or("")))
+            .collect();
+        if completed.is_empty() { "(none)".to_string() } else { completed.join("\n") }
+    }
+
+    fn format_steps<'a>(steps: impl Iterator<Item = (usize, &'a PlanStep)>) -> String {
+        let steps: Vec<String> = steps.map(|(i, step)| format!("{}. {}", i + 1, step.description)).collect();
+        if steps.is_empty() { "(none)".to_string() } else { steps.join("\n") }
+    }
+}
+
+
+impl <M: BaseMemory + Send> PlanExecuteAgent<M> {
+    fn format_tools(&self) -> String {
+        let tools: Vec<String> = self.executor.get_tools_schema().iter().map(|tool| {
+            format!("- {}: {}", tool["name"].as_str().unwrap_or(""), tool["description"].as_str().unwrap_or(""))
+        }).collect();
+        if tools.is_empty() { "(no tools)".to_string() } else { tools.join("\n") }
+    }
+
+    async fn run_plan(&mut self, task: &str) -> String {
+        let run_start = Instant::now();
+        let mut run_usage = Usage::default();
+        self.planner.take_usage();
+        // a trajectory that cannot be written is logged and skipped, the run goes on
+        let mut trajectory = self.trajectory_dir.as_deref().and_then(|dir| {
+            TrajectoryLogger::new(dir).map_err(|e| tracing::error!("Running without a trajectory: {:#}", e)).ok()
+        });
+        if let Some(logger) = trajectory.as_mut() {
+            logger.log(TrajectoryEvent::RunStarted {
+                agent: "PlanExecuteAgent".to_string(),
+                model: self.planner.model_name().to_string(),
+                system_prompt: PLANNER_SYSTEM_PROMPT.to_string(),
+                user_prompt: task.to_string(),
+            });
+        }
+
+        // the step prompts repeat the task, the history should also show what was asked
+        self.executor.add_message(Message::user(task)).await;
+        let plan_prompt = Message::user(&PLAN_PROMPT.replace("{tools}", &self.format_tools()).replace("{task}", task));
+        let steps = match self.planner.call_structured::<PlanDraft>(vec![&plan_prompt], &GenerationOptions::default()).await {
+            Ok(draft) if !draft.steps.is_empty() => draft.steps,
+            Ok(_) => vec![task.to_string()],
+            Err(e) => {
+                tracing::error!("Failed to create a plan: {}. Executing the task as a single step.", e);
+                vec![task.to_string()]
+            }
+        };
+        tracing::debug!("Plan: {:?}", steps);
+        self.plan = steps.clone().into_iter().map(PlanStep::pending).collect();
+        if let Some(logger) = trajectory.as_mut() {
+            logger.log(TrajectoryEvent::PlanCreated { steps });
+        }
+
+        let mut final_answer = None;
+        let mut executed = 0;
+        while let Some(index) = self.plan.iter().position(|step| step.status == StepStatus::Pending) {
+            if executed >= self.max_steps {
+                tracing::error!("Reached max steps {} with {} steps left in the plan", self.max_steps, self.plan.len() - index);
+                break;
+            }
+            executed += 1;
+            let prompt = EXECUTE_STEP_PROMPT.replace("{task}", task)
+                                            .replace("{plan}", &Self::format_steps(self.plan.iter().enumerate()))
+                                            .replace("{completed}", &self.format_completed())
+                                            .replace("{index}", &(index + 1).to_string())
+                                            .replace("{step}", &self.plan[index].description);
+            let step_span = tracing::info_span!("plan_step", r_agent.step = index);
+            let outcome = self.executor.try_run(&prompt).instrument(step_span).await;
+            run_usage.accumulate(self.executor.last_usage());
+            let step = &mut self.plan[index];
+            match outcome {
+                Ok(result) => {
+                    step.status = StepStatus::Done;
+                    step.result = Some(result);
+                },
+                Err(e) => {
+                    tracing::error!("Step {} failed: {}", index + 1, e);
+                    step.status = StepStatus::Failed;
+                    step.result = Some(e.to_string());
+                }
+            }
+            if let Some(logger) = trajectory.as_mut() {
+                logger.log(TrajectoryEvent::StepFinished { index, step: step.clone() });
+            }
+
+            let revise_prompt = Message::user(&REVISE_PLAN_PROMPT.replace("{task}", task)
+                                            .replace("{completed}", &self.format_completed())
+                                            .replace("{remaining}", &Self::format_steps(self.plan.iter().enumerate().skip(index + 1))));
+            match self.planner.call_structured::<PlanRevision>(vec![&revise_prompt], &GenerationOptions::default()).await {
+                Ok(revision) => {
+                    self.plan.truncate(index + 1);
+                    self.plan.extend(revision.remaining_steps.into_iter().map(PlanStep::pending));
+                    if let Some(logger) = trajectory.as_mut() {
+                        logger.log(TrajectoryEvent::PlanRevised { plan: self.plan.clone() });
+                    }
+                    if index + 1 == self.plan.len() && revision.final_answer.is_some() {
+                        final_answer = revision.final_answer;
+                        break;
+                    }
+                },
+                Err(e) => tracing::error!("Failed to revise the plan: {}. Keeping the current plan.", e),
+            }
+        }
+
+        let answer = match final_answer {
+            Some(answer) => answer,
+            None => {
+                let prompt = Message::user(&PLAN_FINAL_ANSWER_PROMPT.replace("{task}", task).replace("{completed}", &self.format_completed()));
+                self.planner.call(&prompt).await.content.unwrap_or_else(|| String::from("Failed to produce a final answer."))
+            }
+        };
+        self.executor.add_message(Message::assistant(&answer, None)).await;
+
+        run_usage.accumulate(&self.planner.take_usage());
+        let span = tracing::Span::current();
+        span.record("gen_ai.usage.input_tokens", run_usage.prompt_tokens);
+        span.record("gen_ai.usage.output_tokens", run_usage.completion_tokens);
+        span.record("r_agent.cost_usd", run_usage.cost_usd);
+        span.record("r_agent.iterations", executed);
+        self.last_usage = run_usage.clone();
+        if let Some(logger) = trajectory.as_mut() {
+            logger.log(TrajectoryEvent::RunFinished {
+                iterations: executed,
+                final_answer: Some(answer.clone()),
+                usage: run_usage,
+                latency_ms: run_start.elapsed().as_millis() as u64,
+            });
+        }
+        answer
+    }
+}
+
+
+#[async_trait]
+impl <M: BaseMemory + Send> BaseAgent for PlanExecuteAgent<M> {
+    async fn add_message(&mut self, message: Message) {
+        self.executor.add_message(message).await;
+    }
+
+    fn build_messages(&self) -> MessageIter<'_> {
+        self.executor.build_messages()
+    }
+
+    fn clear_history(&mut self) {
+        self.executor.clear_history();
+        self.plan.clear();
+    }
+
+    fn get_history(&self) -> MessageIter<'_> {
+        self.executor.get_history()
+    }
+
+    async fn run(&mut self, user_prompt: &str) -> String {
+        let span = tracing::info_span!(
+            "invoke_agent",
+            otel.name = "invoke_agent PlanExecuteAgent",
+            gen_ai.operation.name = "invoke_agent",
+            gen_ai.agent.name = "PlanExecuteAgent",
+            gen_ai.request.model = self.planner.model_name(),
+            gen_ai.usage.input_tokens = tracing::field::Empty,
+            gen_ai.usage.output_tokens = tracing::field::Empty,
+            r_agent.cost_usd = tracing::field::Empty,
+            r_agent.iterations = tracing::field::Empty,
+        );
+        self.run_plan(user_prompt).instrument(span).await
+    }
+
+    fn run_usage(&self) -> Option<Usage> {
+        Some(self.last_usage.clone())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::sliding_window::SlidingWindowMemory, test_support::*, trajectory::logger::Trajectory};
+
+    #[tokio::test]
+    async fn test_plan_execute_revises_plan() {
+        let dir = "./workspace_test/trajectories_plan_execute";
+        let _ = std::fs::remove_dir_all(dir);
+        let config = offline_config();
+        // echoes the step it was given
+        let executor = react_agent(&config, "You are an executor.", SlidingWindowMemory::new(50, MODEL, 100000), FnModel(|history: &[&Message]| {
+            let last = &history.last().unwrap().content;
+            answer(&format!("did {}", last.lines().find(|line| line.starts_with("Now carry out")).unwrap_or("")))
+        }));
+        let planner = ScriptedModel::new([
+            r#"{"steps": ["find the city", "look up the weather", "write a poem"]}"#,
+            r#"{"remaining_steps": ["look up the weather in Oslo"]}"#,
+            r#"{"remaining_steps": [], "final_answer": "Oslo is sunny"}"#,
+        ]);
+        let mut agent = PlanExecuteAgent::new(&config, MODEL, executor, 10)
+                            .map_planner(|_| Box::new(planner))
+                            .with_trajectory_dir(dir);
+
+        let answer = agent.run("What is the weather in the capital of Norway?").await;
+        assert_eq!(answer, "Oslo is sunny");
+        let history: Vec<&Message> = agent.get_history().collect();
+        assert_eq!(history[0].content, "What is the weather in the capital of Norway?");
+        assert_eq!(history.last().unwrap().content, "Oslo is sunny");
+        let plan = agent.plan();
+        assert_eq!(plan.len(), 2);
+        assert!(plan.iter().all(|step| step.status == StepStatus::Done));
+        assert_eq!(plan[1].description, "look up the weather in Oslo");
+        assert_eq!(plan[1].result.as_deref(), Some("did Now carry out step 2 only: look up the weather in Oslo"));
+
+        let trajectory = Trajectory::load(&Trajectory::list(std::path::Path::new(dir)).unwrap()[0]).unwrap();
+        let events: Vec<String> = trajectory.records.iter().map(|r| serde_json::to_value(r).unwrap()["event"].as_str().unwrap().to_string()).collect();
+        assert_eq!(events, vec!["run_started", "plan_created", "step_finished", "plan_revised", "step_finished", "plan_revised", "run_finished"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,119 +1,241 @@
 use anyhow::Context;
 use llm::ToolCall;
 use serde_json::Value;
+use std::{path::PathBuf, time::Instant};
 use async_trait::async_trait;
-use crate::{agent::{base::BaseAgent, tool_agent::ToolAgent}, 
-            config::config::Config, memory::base::BaseMemory, 
-            model::{base::BaseModel, litellm_model::LitellmModel, schema::{LLMResponse, Message}},
+use tracing::Instrument;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use crate::{agent::{base::BaseAgent, handoff::handoff_tool_schema, termination::Termination, tool_agent::ToolAgent, typed::*}, 
+            config::config::{Config, ModelConfig}, memory::base::{BaseMemory, MessageIter}, 
+            model::{base::BaseModel, cache::CachedModel, litellm_model::LitellmModel, options::GenerationOptions, schema::{Message, Usage}, structured::DEFAULT_STRUCTURED_ATTEMPTS},
             prompt::agent::*, 
-            tool::manager::ToolManager};
+            tool::manager::ToolManager,
+            trajectory::{logger::TrajectoryLogger, schema::TrajectoryEvent}};
 
 
  pub struct ReactAgent<M: BaseMemory> {
-    model: LitellmModel,
+    model: Box<dyn BaseModel>,
+    model_name: String,
+    model_config: ModelConfig,
+    // the prompt given by the user, `system_prompt` wraps it into the ReAct instructions
+    base_prompt: String,
     system_prompt: Message,
     max_iterations: usize,
     tool_manager: ToolManager,
     tool_names: Vec<String>,
     memory: M,
+    trajectory_dir: Option<PathBuf>,
+    options: GenerationOptions,
+    answer_attempts: usize,
+    termination: Termination,
+    last_usage: Usage,
+    // `transfer_to_<agent>` tool schemas offered on untyped runs
+    handoffs: Vec<Value>,
+    // the agent the latest run transferred the conversation to
+    handoff: Option<String>,
 }
 
 
 impl <M: BaseMemory> ReactAgent<M> {
     pub fn new(config: &Config, model_name: &str, system_prompt: &str, max_iterations: usize, tool_manager: ToolManager, memory: M, tool_names: Vec<String>) -> Self {
-        let model_config = config.models.get(model_name).expect(format!("Model {} not found in config", model_name).as_str());
-        let model = LitellmModel::new(model_name, model_config, system_prompt);
+        let model_config = config.models.get(model_name).unwrap_or_else(|| panic!("Model {} not found in config", model_name)).clone();
+        let termination = Termination::default();
+        let full_prompt = Self::build_system_prompt(&termination, system_prompt);
         let tool_schemas = tool_manager.get_schema(&tool_names);
+        let model = Self::build_model(model_name, &model_config, &full_prompt, tool_schemas, &termination);
 
-        let mut ret = Self {
+        Self {
             model,
-            system_prompt: Message::system(system_prompt),
+            model_name: model_name.to_string(),
+            model_config,
+            base_prompt: system_prompt.to_string(),
+            system_prompt: Message::system(&full_prompt),
             max_iterations,
             tool_manager,
             tool_names,
             memory,
-        };
-        ret.system_prompt.content = ret.build_system_prompt(&ret.system_prompt.content);
-        
-        if tool_schemas.len() > 0 {
-            let model = LitellmModel::new_with_tools(model_name, model_config, &ret.system_prompt.content, tool_schemas);
-            ret.model = model;
-        }else{
-            let model = LitellmModel::new(model_name, model_config, &ret.system_prompt.content);
-            ret.model = model;
+            trajectory_dir: None,
+            options: GenerationOptions::default(),
+            answer_attempts: DEFAULT_STRUCTURED_ATTEMPTS,
+            termination,
+            last_usage: Usage::default(),
+            handoffs: Vec::new(),
+            handoff: None,
         }
-        ret
     }
 
-    fn build_system_prompt(&self, user_prompt: &str) -> String {
-        format!("{}\n\nUser Prompt: {}", REACT_SYSTEM_PROMPT.as_str(), user_prompt)
+    fn build_model(model_name: &str, model_config: &ModelConfig, system_prompt: &str, mut tool_schemas: Vec<Value>, termination: &Termination) -> Box<dyn BaseModel> {
+        tool_schemas.extend(termination.tool_schemas());
+        let model = LitellmModel::new_with_tools(model_name, model_config, system_prompt, tool_schemas);
+        CachedModel::wrap_if_enabled(Box::new(model), model_config)
     }
 
-    fn is_finished(&self, content: &LLMResponse) -> bool {
-        match content.content.as_ref() {
-            Some(data) => {
-                data.contains(REACT_END_TOKEN)
-            },
-            None => false,
-        }
+    /// How the agent recognizes the final answer, the system prompt and tools of the model follow it.
+    /// This rebuilds the model, so call it before `map_model`.
+    pub fn with_termination(mut self, termination: Termination) -> Self {
+        self.system_prompt.content = Self::build_system_prompt(&termination, &self.base_prompt);
+        let tool_schemas = self.tool_manager.get_schema(&self.tool_names);
+        self.model = Self::build_model(&self.model_name, &self.model_config, &self.system_prompt.content, tool_schemas, &termination);
+        self.termination = termination;
+        self
     }
 
-    fn extract_final_answer(&self, content: &LLMResponse) -> Option<String> {
-        let content = content.content.as_ref()?;
-        if let Some(end_index) = content.find(REACT_END_TOKEN) {
-            let answer = &content[..end_index];
-            Some(answer.trim().to_string())
-        } else {
-            None
-        }
+    /// Replace the model with a wrapper around it, e.g. a `RecordingModel`
+    pub fn map_model<F: FnOnce(Box<dyn BaseModel>) -> Box<dyn BaseModel>>(mut self, f: F) -> Self {
+        self.model = f(self.model);
+        self
+    }
+
+    /// Write a JSONL trajectory of every run into `dir`, one file per run
+    pub fn with_trajectory_dir(mut self, dir: &str) -> Self {
+        self.trajectory_dir = Some(PathBuf::from(dir));
+        self
+    }
+
+    /// Generation options for every model call of this agent, layered on the model config defaults
+    pub fn with_options(mut self, options: GenerationOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// How often `run_typed` lets the model correct an invalid final answer
+    pub fn with_answer_attempts(mut self, attempts: usize) -> Self {
+        self.answer_attempts = attempts.max(1);
+        self
+    }
+
+    /// Total usage of the latest run
+    pub fn last_usage(&self) -> &Usage {
+        &self.last_usage
+    }
+
+    /// Offer a `transfer_to_<name>` tool for every `(name, description)`, calling one ends the run.
+    /// Usually set by a `HandoffRouter`.
+    pub fn set_handoffs(&mut self, targets: &[(String, String)]) {
+        self.handoffs = targets.iter().map(|(name, description)| handoff_tool_schema(name, description)).collect();
+    }
+
+    /// The agent the latest run transferred the conversation to, cleared by this call
+    pub fn take_handoff(&mut self) -> Option<String> {
+        self.handoff.take()
+    }
+
+    fn build_system_prompt(termination: &Termination, user_prompt: &str) -> String {
+        format!("{}\n\nUser Prompt: {}", termination.system_prompt(), user_prompt)
     }
 }
 
 
-#[async_trait]
-impl <M: BaseMemory + Send> BaseAgent for ReactAgent<M> {
-   
-   async fn add_message(&mut self, message: Message) {
-        self.memory.add(message).await;   
-   } 
-   
-   fn build_messages(&self) -> impl Iterator<Item =  &Message> {
-        self.get_history()
-   }
+impl <M: BaseMemory + Send> ReactAgent<M> {
+    /// Run until the model calls the `final_answer` tool with arguments matching the JSON schema of `T`.
+    /// Invalid arguments are sent back to the model, up to `with_answer_attempts` times.
+    pub async fn run_typed<T: DeserializeOwned + JsonSchema>(&mut self, user_prompt: &str) -> Result<T, TypedRunError> {
+        let (parameters, wrapped) = answer_schema::<T>();
+        let validate = move |arguments: &str| parse_answer::<T>(arguments, wrapped).map(|_| ());
+        let tool = AnswerTool { schema: answer_tool_schema(parameters), validate: &validate, max_attempts: self.answer_attempts };
+        let span = self.invoke_span();
+        let (arguments, attempts) = self.run_react(user_prompt, Some(&tool)).instrument(span).await?;
+        parse_answer::<T>(&arguments, wrapped).map_err(|message| TypedRunError::InvalidAnswer { attempts, message })
+    }
 
-   fn clear_history(&mut self) {
-        self.memory.clear();
-   }
+    /// Same as `run`, but a run without a final answer is an error instead of a placeholder answer
+    pub async fn try_run(&mut self, user_prompt: &str) -> Result<String, TypedRunError> {
+        let span = self.invoke_span();
+        self.run_react(user_prompt, None).instrument(span).await.map(|(answer, _)| answer)
+    }
 
-   fn get_history(&self) -> impl Iterator<Item = &Message> {
-        self.memory.get_messages()
-   }
+    fn handoff_target(&self, function_name: &str) -> Option<String> {
+        let target = function_name.strip_prefix(HANDOFF_TOOL_PREFIX)?;
+        self.handoffs.iter().any(|tool| tool["name"] == function_name).then(|| target.to_string())
+    }
 
-   async fn run(&mut self, user_prompt: &str) -> String{
+    fn invoke_span(&self) -> tracing::Span {
+        tracing::info_span!(
+            "invoke_agent",
+            otel.name = "invoke_agent ReactAgent",
+            gen_ai.operation.name = "invoke_agent",
+            gen_ai.agent.name = "ReactAgent",
+            gen_ai.request.model = self.model.model_name(),
+            gen_ai.usage.input_tokens = tracing::field::Empty,
+            gen_ai.usage.output_tokens = tracing::field::Empty,
+            r_agent.cost_usd = tracing::field::Empty,
+            r_agent.iterations = tracing::field::Empty,
+        )
+    }
+
+    /// The ReAct loop, a run ends with the end token or, when `answer_tool` is given, with a valid `final_answer` call.
+    /// Returns the final answer, the raw `final_answer` arguments for typed runs, and the number of `final_answer` calls.
+    async fn run_react(&mut self, user_prompt: &str, answer_tool: Option<&AnswerTool<'_>>) -> Result<(String, usize), TypedRunError> {
         tracing::debug!("Current memory: {:?}", self.memory.get_messages().collect::<Vec<&Message>>());
-        self.add_message(Message::user(user_prompt)).await;
+        let mut options = self.options.clone();
+        match answer_tool {
+            Some(tool) => {
+                self.add_message(Message::user(&format!("{}\n\n{}", user_prompt, TYPED_ANSWER_PROMPT))).await;
+                options.tools.get_or_insert_with(Vec::new).push(tool.schema.clone());
+            },
+            None => {
+                self.add_message(Message::user(user_prompt)).await;
+                if !self.handoffs.is_empty() {
+                    options.tools.get_or_insert_with(Vec::new).extend(self.handoffs.iter().cloned());
+                }
+            },
+        }
+        self.handoff = None;
         tracing::debug!("Running ReactAgent with user prompt: {}", user_prompt);
 
+        let run_start = Instant::now();
+        let mut run_usage = Usage::default();
+        // a trajectory that cannot be written is logged and skipped, the run goes on
+        let mut trajectory = self.trajectory_dir.as_deref().and_then(|dir| {
+            TrajectoryLogger::new(dir).map_err(|e| tracing::error!("Running without a trajectory: {:#}", e)).ok()
+        });
+        if let Some(logger) = trajectory.as_mut() {
+            logger.log(TrajectoryEvent::RunStarted {
+                agent: "ReactAgent".to_string(),
+                model: self.model.model_name().to_string(),
+                system_prompt: self.system_prompt.content.clone(),
+                user_prompt: user_prompt.to_string(),
+            });
+        }
+
+        let mut final_answer = None;
+        let mut iterations = 0;
+        let mut rejected_answers = 0;
+        let mut last_rejection = String::new();
         for i in 0..self.max_iterations {
+            iterations = i + 1;
             let msgs: Vec<&Message> = self.build_messages().collect();
+            let sent = trajectory.as_ref().map(|_| msgs.iter().map(|m| (*m).clone()).collect::<Vec<Message>>());
             tracing::debug!("Current memory: {:?}", self.memory.get_messages().collect::<Vec<&Message>>());
             tracing::debug!("Iteration {}/{}", i + 1, self.max_iterations);
-            let response = self.model.call_with_history(msgs).await;
+            let iteration_span = tracing::info_span!("react_iteration", r_agent.iteration = i);
+            let call_start = Instant::now();
+            let response = self.model.call_with_options(msgs, &options).instrument(iteration_span.clone()).await;
+            if let Some(usage) = &response.usage {
+                run_usage.accumulate(usage);
+            }
+            if let (Some(logger), Some(messages)) = (trajectory.as_mut(), sent) {
+                logger.log(TrajectoryEvent::ModelCall {
+                    iteration: i,
+                    messages,
+                    response: response.clone(),
+                    latency_ms: call_start.elapsed().as_millis() as u64,
+                });
+            }
             
             // response is formmatted well for react agent
-            if self.is_finished(&response){
-                let final_answer = self.extract_final_answer(&response);
-                if let Some(answer) = final_answer {
-                    self.add_message(Message::assistant(&answer, None)).await;
-                    tracing::debug!("Final answer extracted: {}", answer);
-                    return answer;
-                }
+            if answer_tool.is_none() && let Some(answer) = self.termination.final_answer(&response) {
+                self.add_message(Message::assistant(&answer, None)).await;
+                tracing::debug!("Final answer extracted: {}", answer);
+                final_answer = Some(answer);
+                break;
             }
             let content = response.content.as_ref().unwrap_or(&"Nothing".to_string()).to_string();
             let reasoning = response.reasoning_content.as_ref().unwrap_or(&"Nothing".to_string()).to_string();
             let content = format!("Content: {} Reasoning:{}", reasoning, content);
             // no tool calling
-            if (&response).tool_calls.is_none(){
+            if response.tool_calls.is_none(){
                 self.add_message(Message::assistant(content.as_str(), None)).await;
                 tracing::debug!("Response (no tools): {}", content.as_str());
                 continue;
@@ -126,19 +248,141 @@ impl <M: BaseMemory + Send> BaseAgent for ReactAgent<M> {
             self.add_message(Message::assistant(&content, Some(tool_calls.clone()))).await;            
             
             tracing::debug!("Tool calls: {:?}", formatted);
+            // every call gets a result, also those after the one that ends the run
+            let mut answered = 0;
             for tc in tool_calls.iter(){
+                answered += 1;
                 let id = &tc.id;
                 let function_name = &tc.function.name;
                 let arguments = &tc.function.arguments;
                 tracing::debug!("Executing tool: {}#{}", id, function_name);
-                let result = self.execute_tool(function_name, arguments).unwrap_or(String::from("No output from tool."));
+                let tool_start = Instant::now();
+                if let Some(tool) = answer_tool && function_name == FINAL_ANSWER_TOOL {
+                    let result = match (tool.validate)(arguments) {
+                        Ok(()) => {
+                            final_answer = Some(arguments.clone());
+                            String::from("Final answer accepted.")
+                        },
+                        Err(e) => {
+                            tracing::error!("Final answer rejected: {}", e);
+                            rejected_answers += 1;
+                            last_rejection = e;
+                            FINAL_ANSWER_RETRY_PROMPT.replace("{error}", &last_rejection)
+                        }
+                    };
+                    if let Some(logger) = trajectory.as_mut() {
+                        logger.log(TrajectoryEvent::ToolCall {
+                            iteration: i,
+                            tool_call: tc.clone(),
+                            output: result.clone(),
+                            latency_ms: tool_start.elapsed().as_millis() as u64,
+                        });
+                    }
+                    self.add_message(Message::tool(&result, Some(vec![tc.clone()]), Some(id.clone()))).await;
+                    if final_answer.is_some() || rejected_answers >= tool.max_attempts {
+                        break;
+                    }
+                    continue;
+                }
+                if answer_tool.is_none() && let Some(target) = self.handoff_target(function_name) {
+                    let result = HANDOFF_RESULT.replace("{agent}", &target);
+                    tracing::debug!("Handing off to {}", target);
+                    if let Some(logger) = trajectory.as_mut() {
+                        logger.log(TrajectoryEvent::ToolCall {
+                            iteration: i,
+                            tool_call: tc.clone(),
+                            output: result.clone(),
+                            latency_ms: tool_start.elapsed().as_millis() as u64,
+                        });
+                    }
+                    self.add_message(Message::tool(&result, Some(vec![tc.clone()]), Some(id.clone()))).await;
+                    self.handoff = Some(target);
+                    final_answer = Some(result);
+                    break;
+                }
+                let tool_span = tracing::info_span!(
+                    parent: &iteration_span,
+                    "execute_tool",
+                    otel.name = format!("execute_tool {}", function_name),
+                    gen_ai.operation.name = "execute_tool",
+                    gen_ai.tool.name = function_name.as_str(),
+                    gen_ai.tool.call.id = id.as_str(),
+                    r_agent.iteration = i,
+                );
+                let result = tool_span.in_scope(|| self.execute_tool(function_name, arguments)).unwrap_or(String::from("No output from tool."));
                 tracing::debug!("Tool result: {}#{:?}", id, result);
+                if let Some(usage) = self.tool_manager.get_tool(function_name).and_then(|tool| tool.take_usage()) {
+                    run_usage.accumulate(&usage);
+                }
+                if let Some(logger) = trajectory.as_mut() {
+                    logger.log(TrajectoryEvent::ToolCall {
+                        iteration: i,
+                        tool_call: tc.clone(),
+                        output: result.clone(),
+                        latency_ms: tool_start.elapsed().as_millis() as u64,
+                    });
+                }
                 self.add_message(Message::tool(&result, Some(vec![tc.clone()]), Some(id.clone()))).await;
             }
-
+            for tc in &tool_calls[answered..] {
+                self.add_message(Message::tool(SKIPPED_TOOL_RESULT, Some(vec![tc.clone()]), Some(tc.id.clone()))).await;
+            }
+            if final_answer.is_some() || answer_tool.is_some_and(|tool| rejected_answers >= tool.max_attempts) {
+                break;
+            }
+        }
+        let span = tracing::Span::current();
+        span.record("gen_ai.usage.input_tokens", run_usage.prompt_tokens);
+        span.record("gen_ai.usage.output_tokens", run_usage.completion_tokens);
+        span.record("r_agent.cost_usd", run_usage.cost_usd);
+        span.record("r_agent.iterations", iterations);
+        self.last_usage = run_usage.clone();
+        if let Some(logger) = trajectory.as_mut() {
+            logger.log(TrajectoryEvent::RunFinished {
+                iterations,
+                final_answer: final_answer.clone(),
+                usage: run_usage,
+                latency_ms: run_start.elapsed().as_millis() as u64,
+            });
         }
-        return String::from("Reached maximum iterations without a final answer.");
+        match final_answer {
+            Some(answer) => Ok((answer, rejected_answers + 1)),
+            None if answer_tool.is_some_and(|tool| rejected_answers >= tool.max_attempts) => {
+                Err(TypedRunError::InvalidAnswer { attempts: rejected_answers, message: last_rejection })
+            },
+            None => Err(TypedRunError::MaxIterations { iterations }),
+        }
+    }
+}
+
+
+#[async_trait]
+impl <M: BaseMemory + Send> BaseAgent for ReactAgent<M> {
+   
+   async fn add_message(&mut self, message: Message) {
+        self.memory.add(message).await;   
+   } 
+   
+   fn build_messages(&self) -> MessageIter<'_> {
+        self.get_history()
+   }
+
+   fn clear_history(&mut self) {
+        self.memory.clear();
+   }
+
+   fn get_history(&self) -> MessageIter<'_> {
+        self.memory.get_messages()
+   }
+
+   async fn run(&mut self, user_prompt: &str) -> String{
+        self.try_run(user_prompt).await
+            .unwrap_or_else(|_| String::from("Reached maximum iterations without a final answer."))
    }  
+
+   fn run_usage(&self) -> Option<Usage> {
+        Some(self.last_usage.clone())
+   }
 }
 
 
@@ -162,6 +406,225 @@ impl <M: BaseMemory + Send> ToolAgent for ReactAgent<M> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{collections::HashMap, sync::{Arc, Mutex}};
+    use crate::memory::sliding_window::SlidingWindowMemory;
+    use crate::model::{recording::RecordingModel, schema::{LLMResponse, Role}};
+    use crate::{test_support::{answer, offline_config, react_agent, tool_call, tool_calls, usage, FnModel, ScriptedModel, MODEL},
+                tool::base::Tool, trajectory::logger::Trajectory};
+
+    #[tokio::test]
+    async fn test_react_agent_replay() {
+        let config = offline_config();
+        let cassette = "./workspace_test/cassettes/react_agent_replay.json";
+        let _ = std::fs::remove_file(cassette);
+        let new_agent = || ReactAgent::new(
+            &config,
+            "gpt-4o-mini",
+            "You are a React Agent.",
+            3,
+            ToolManager::new(Vec::new()),
+            SlidingWindowMemory::new(10, "gpt-4o-mini", 8192),
+            Vec::new()
+        );
+
+        let mut recorder = new_agent().map_model(|model| {
+            // stands in for the real model while recording
+            let scripted = ScriptedModel::new([format!("You said: hello {}", REACT_END_TOKEN)]).with_system_prompt(model.system_prompt());
+            Box::new(RecordingModel::record(Box::new(scripted), cassette).unwrap())
+        });
+        let recorded = recorder.run("hello").await;
+        assert_eq!(recorded, "You said: hello");
+        // the cassette is written when the recording model is dropped
+        drop(recorder);
+
+        // the replayed agent never reaches the network, its real model has a fake key
+        let mut player = new_agent().map_model(|model| Box::new(RecordingModel::replay(model, cassette).unwrap()));
+        let replayed = player.run("hello").await;
+        assert_eq!(replayed, recorded);
+        std::fs::remove_file(cassette).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_react_agent_options() {
+        let config = offline_config();
+        let model = ScriptedModel::new([format!("hello {}", REACT_END_TOKEN)]);
+        let requests = model.requests();
+        let options = GenerationOptions::new().temperature(0.0).seed(42);
+        let mut agent = ReactAgent::new(
+            &config,
+            "gpt-4o-mini",
+            "You are a React Agent.",
+            3,
+            ToolManager::new(Vec::new()),
+            SlidingWindowMemory::new(10, "gpt-4o-mini", 8192),
+            Vec::new()
+        ).with_options(options.clone())
+         .map_model(|_| Box::new(model));
+        agent.run("hello").await;
+        let seen_options: Vec<GenerationOptions> = requests.lock().unwrap().iter().map(|(_, options)| options.clone()).collect();
+        assert_eq!(seen_options, vec![options]);
+    }
+
+    // answers every call with a call of `tool`, taking the arguments from the script
+    struct FinalAnswerModel {
+        tool: &'static str,
+        arguments: Mutex<Vec<&'static str>>,
+        seen_tools: Arc<Mutex<Vec<Value>>>,
+    }
+
+    #[async_trait]
+    impl BaseModel for FinalAnswerModel {
+        async fn call(&self, user_prompt: &Message) -> LLMResponse {
+            self.call_with_history(vec![user_prompt]).await
+        }
+
+        async fn call_with_history(&self, history: Vec<&Message>) -> LLMResponse {
+            self.call_with_options(history, &GenerationOptions::default()).await
+        }
+
+        async fn call_with_options(&self, _history: Vec<&Message>, options: &GenerationOptions) -> LLMResponse {
+            self.seen_tools.lock().unwrap().extend(options.tools.iter().flatten().cloned());
+            let mut arguments = self.arguments.lock().unwrap();
+            let n = arguments.len();
+            let call = tool_call(&format!("call_{}", n), self.tool, arguments.remove(0));
+            LLMResponse { content: None, reasoning_content: None, usage: None, tool_calls: Some(vec![call]) }
+        }
+
+        fn model_name(&self) -> &str {
+            "gpt-4o-mini"
+        }
+    }
+
+    #[derive(Debug, serde::Deserialize, JsonSchema, PartialEq)]
+    struct Forecast {
+        city: String,
+        celsius: f32,
+    }
+
+    #[tokio::test]
+    async fn test_react_agent_run_typed() {
+        let config = offline_config();
+        let new_agent = |arguments: Vec<&'static str>, seen_tools: Arc<Mutex<Vec<Value>>>| ReactAgent::new(
+            &config,
+            "gpt-4o-mini",
+            "You are a React Agent.",
+            5,
+            ToolManager::new(Vec::new()),
+            SlidingWindowMemory::new(20, "gpt-4o-mini", 8192),
+            Vec::new()
+        ).with_answer_attempts(2)
+         .map_model(|_| Box::new(FinalAnswerModel { tool: FINAL_ANSWER_TOOL, arguments: Mutex::new(arguments), seen_tools }));
+
+        let seen_tools = Arc::new(Mutex::new(Vec::new()));
+        let mut agent = new_agent(vec![r#"{"city": "Oslo"}"#, r#"{"city": "Oslo", "celsius": 3.5}"#], seen_tools.clone());
+        let forecast: Forecast = agent.run_typed("weather in Oslo?").await.unwrap();
+        assert_eq!(forecast, Forecast { city: "Oslo".to_string(), celsius: 3.5 });
+        assert_eq!(seen_tools.lock().unwrap()[0]["name"], FINAL_ANSWER_TOOL);
+        let rejection = agent.get_history().find(|m| m.content.contains("rejected")).unwrap();
+        assert!(rejection.content.contains("missing field `celsius`"));
+
+        let mut agent = new_agent(vec![r#"{"city": 1}"#, "not json"], Arc::default());
+        let err = agent.run_typed::<Forecast>("weather in Oslo?").await.unwrap_err();
+        assert!(matches!(err, TypedRunError::InvalidAnswer { attempts: 2, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_react_agent_answers_calls_after_final_answer() {
+        let config = offline_config();
+        let model = FnModel(|_: &[&Message]| tool_calls(vec![
+            tool_call("call_0", FINAL_ANSWER_TOOL, r#"{"city": "Oslo", "celsius": 3.5}"#),
+            tool_call("call_1", "lookup", "{}"),
+        ]));
+        let mut agent = react_agent(&config, "You are a React Agent.", SlidingWindowMemory::new(20, MODEL, 8192), model);
+        let forecast: Forecast = agent.run_typed("weather in Oslo?").await.unwrap();
+        assert_eq!(forecast.city, "Oslo");
+        let results: Vec<(&str, &str)> = agent.get_history().filter(|m| m.role == Role::TOOL)
+                                              .map(|m| (m.tool_call_id.as_deref().unwrap(), m.content.as_str())).collect();
+        assert_eq!(results, vec![("call_0", "Final answer accepted."), ("call_1", SKIPPED_TOOL_RESULT)]);
+    }
+
+    #[derive(Debug)]
+    struct WeatherTool(Value);
+
+    impl WeatherTool {
+        fn new() -> Self {
+            WeatherTool(serde_json::json!({
+                "name": "weather",
+                "description": "Current weather of a city",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]},
+            }))
+        }
+    }
+
+    impl Tool for WeatherTool {
+        fn load(&self) -> &Value {
+            &self.0
+        }
+
+        fn init(&mut self) {}
+
+        fn execute(&self, input: &str) -> String {
+            format!("sunny in {}", serde_json::from_str::<Value>(input).unwrap()["city"].as_str().unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_react_agent_trajectory() {
+        let dir = "./workspace_test/trajectories_react_agent";
+        let _ = std::fs::remove_dir_all(dir);
+        let config = offline_config();
+        // calls the tool first, then answers with its result
+        let model = FnModel(|history: &[&Message]| match history.last().unwrap().role {
+            Role::TOOL => LLMResponse { usage: Some(usage(30)), ..answer(&history.last().unwrap().content) },
+            _ => LLMResponse { usage: Some(usage(20)), ..tool_calls(vec![tool_call("call_0", "weather", r#"{"city": "Oslo"}"#)]) },
+        });
+        let mut agent = ReactAgent::new(&config, MODEL, "You are a React Agent.", 3, ToolManager::new(vec![Box::new(WeatherTool::new())]),
+                                        SlidingWindowMemory::new(10, MODEL, 8192), Vec::new())
+                            .map_model(|_| Box::new(model))
+                            .with_trajectory_dir(dir);
+        assert_eq!(agent.run("weather in Oslo?").await, "sunny in Oslo");
+
+        let trajectory = Trajectory::load(&Trajectory::list(std::path::Path::new(dir)).unwrap()[0]).unwrap();
+        let events: Vec<String> = trajectory.records.iter().map(|r| serde_json::to_value(r).unwrap()["event"].as_str().unwrap().to_string()).collect();
+        assert_eq!(events, vec!["run_started", "model_call", "tool_call", "model_call", "run_finished"]);
+        let Some(TrajectoryEvent::ToolCall { iteration, tool_call, output, .. }) = trajectory.records.iter().map(|r| &r.event).find(|e| matches!(e, TrajectoryEvent::ToolCall { .. })) else {
+            unreachable!()
+        };
+        assert_eq!((*iteration, tool_call.function.name.as_str(), output.as_str()), (0, "weather", "sunny in Oslo"));
+        assert_eq!(trajectory.total_usage().total_tokens, 50);
+        assert_eq!(trajectory.final_answer(), Some("sunny in Oslo"));
+        let Some(TrajectoryEvent::RunFinished { iterations, usage, .. }) = trajectory.records.last().map(|r| &r.event) else {
+            unreachable!()
+        };
+        assert_eq!((*iterations, usage.total_tokens), (2, 50));
+
+        // an unwritable directory only costs the trajectory
+        std::fs::write(format!("{}/blocked", dir), "").unwrap();
+        let mut agent = agent.with_trajectory_dir(&format!("{}/blocked/nested", dir));
+        assert_eq!(agent.run("weather in Oslo?").await, "sunny in Oslo");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_react_agent_finish_tool() {
+        let config = offline_config();
+        let mut agent = ReactAgent::new(
+            &config,
+            "gpt-4o-mini",
+            "You are a React Agent.",
+            3,
+            ToolManager::new(Vec::new()),
+            SlidingWindowMemory::new(10, "gpt-4o-mini", 8192),
+            Vec::new()
+        ).with_termination(Termination::FinishTool)
+         .map_model(|model| {
+            assert!(model.tool_schemas().iter().any(|tool| tool["name"] == FINISH_TOOL));
+            assert!(!model.system_prompt().contains(REACT_END_TOKEN));
+            Box::new(FinalAnswerModel { tool: FINISH_TOOL, arguments: Mutex::new(vec![r#"{"answer": "42"}"#]), seen_tools: Arc::default() })
+        });
+        assert_eq!(agent.run("the answer?").await, "42");
+    }
+
     #[tokio::test]
     async fn test_react_agent_run() {
         let config = crate::config::config::load_config(None);
@@ -219,4 +682,66 @@ mod tests {
         let follow_up_answer = agent2.run(&follow_up_prompt).await;
         println!("Follow-up Agent Answer: {}", follow_up_answer);
     }
-}
\ No newline at end of file
+
+    type CollectedSpan = (tracing::span::Id, String, HashMap<String, String>);
+
+    // collects span names and their fields, stands in for an OTLP collector
+    #[derive(Clone, Default)]
+    struct SpanCollector {
+        spans: std::sync::Arc<std::sync::Mutex<Vec<CollectedSpan>>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value).trim_matches('"').to_string());
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanCollector {
+        fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, id: &tracing::span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let mut fields = HashMap::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            self.spans.lock().unwrap().push((id.clone(), attrs.metadata().name().to_string(), fields));
+        }
+
+        fn on_record(&self, id: &tracing::span::Id, values: &tracing::span::Record<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+            let mut spans = self.spans.lock().unwrap();
+            if let Some((_, _, fields)) = spans.iter_mut().find(|(span_id, _, _)| span_id == id) {
+                values.record(&mut FieldVisitor(fields));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_react_agent_spans() {
+        use tracing_subscriber::layer::SubscriberExt;
+        let collector = SpanCollector::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(collector.clone()));
+
+        let config = offline_config();
+        let mut agent = ReactAgent::new(
+            &config,
+            "gpt-4o-mini",
+            "You are a React Agent.",
+            1,
+            ToolManager::new(Vec::new()),
+            SlidingWindowMemory::new(10, "gpt-4o-mini", 8192),
+            Vec::new()
+        );
+        agent.run("hello").await;
+
+        let spans = collector.spans.lock().unwrap();
+        let find = |name: &str| spans.iter().find(|(_, n, _)| n == name).map(|(_, _, fields)| fields.clone()).unwrap();
+        let run = find("invoke_agent");
+        assert_eq!(run["gen_ai.agent.name"], "ReactAgent");
+        assert_eq!(run["gen_ai.request.model"], "gpt-4o-mini");
+        assert_eq!(run["r_agent.iterations"], "1");
+        assert_eq!(find("react_iteration")["r_agent.iteration"], "0");
+        let chat = find("chat");
+        assert_eq!(chat["otel.name"], "chat gpt-4o-mini");
+        assert_eq!(chat["gen_ai.operation.name"], "chat");
+        assert_eq!(chat["otel.status_code"], "ERROR");
+    }
+}
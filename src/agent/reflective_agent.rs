@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use serde_json::json;
+use std::sync::Arc;
+use async_trait::async_trait;
+use tracing::Instrument;
+use crate::{agent::base::BaseAgent, config::config::Config, memory::base::MessageIter,
+            model::{base::BaseModel, cache::CachedModel, litellm_model::LitellmModel, metered::MeteredModel, options::GenerationOptions, schema::{Message, Usage}, structured::StructuredOutput},
+            prompt::reflect::*,
+            tool::base::Tool};
+
+/// Checks an answer to a task before the critic sees it, `Err` fails the round with the message as critique
+pub type AnswerChecker = Arc<dyn Fn(&str, &str) -> Result<(), String> + Send + Sync>;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Critique {
+    /// Whether the answer fully and correctly solves the task
+    passed: bool,
+    /// What is wrong with the answer and how to fix it, empty when it passed
+    critique: String,
+}
+
+/// The outcome of one round of a `ReflectiveAgent` run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reflection {
+    pub round: usize,
+    pub answer: String,
+    pub passed: bool,
+    pub critique: String,
+}
+
+/// Reflexion-style wrapper around any agent
+/// After each answer of the inner agent a checker function, a test tool and a critic model review it.
+/// On failure the critique is added to the inner agent's memory and the task is retried, up to `max_rounds` answers.
+/// If the critic itself fails the round fails with the error as critique and the run stops there.
+pub struct ReflectiveAgent<A: BaseAgent> {
+    inner: A,
+    critic: MeteredModel,
+    max_rounds: usize,
+    checker: Option<AnswerChecker>,
+    // called with `{"task": ..., "answer": ...}`, its output is shown to the critic
+    test_tool: Option<Box<dyn Tool>>,
+    reflections: Vec<Reflection>,
+    last_usage: Usage,
+}
+
+impl <A: BaseAgent> ReflectiveAgent<A> {
+    pub fn new(config: &Config, critic_model: &str, inner: A, max_rounds: usize) -> Self {
+        let model_config = config.models.get(critic_model).unwrap_or_else(|| panic!("Model {} not found in config", critic_model));
+        let critic = CachedModel::wrap_if_enabled(Box::new(LitellmModel::new(critic_model, model_config, CRITIC_SYSTEM_PROMPT)), model_config);
+        ReflectiveAgent {
+            inner,
+            critic: MeteredModel::new(critic),
+            max_rounds: max_rounds.max(1),
+            checker: None,
+            test_tool: None,
+            reflections: Vec::new(),
+            last_usage: Usage::default(),
+        }
+    }
+
+    /// Like `ReactAgent::map_model`, for the critic
+    pub fn map_critic<F: FnOnce(Box<dyn BaseModel>) -> Box<dyn BaseModel>>(mut self, f: F) -> Self {
+        self.critic = self.critic.map_inner(f);
+        self
+    }
+
+    /// `checker(task, answer)` runs before the critic, a failed check skips the critic
+    pub fn with_checker<F: Fn(&str, &str) -> Result<(), String> + Send + Sync + 'static>(mut self, checker: F) -> Self {
+        self.checker = Some(Arc::new(checker));
+        self
+    }
+
+    pub fn with_test_tool(mut self, tool: Box<dyn Tool>) -> Self {
+        self.test_tool = Some(tool);
+        self
+    }
+
+    /// The rounds of the latest run in order, the last one holds the returned answer
+    pub fn reflections(&self) -> &[Reflection] {
+        &self.reflections
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Total usage of the latest run, all rounds of the inner agent and the critic
+    pub fn last_usage(&self) -> &Usage {
+        &self.last_usage
+    }
+
+    /// Runs the checker and the test tool, `Err` is a failed check, `Ok` the test results for the critic
+    fn check(&self, task: &str, answer: &str) -> Result<String, String> {
+        if let Some(checker) = self.checker.as_ref() {
+            checker(task, answer)?;
+        }
+        Ok(match self.test_tool.as_ref() {
+            Some(tool) => {
+                let results = tool.execute(&json!({"task": task, "answer": answer}).to_string());
+                TEST_RESULTS_PROMPT.replace("{results}", &results)
+            },
+            None => String::new(),
+        })
+    }
+
+    // the test tool is not `Sync`, so the critic call only borrows the critic
+    async fn critique(critic: &MeteredModel, task: &str, answer: &str, checks: &str) -> Result<(bool, String), String> {
+        let prompt = Message::user(&CRITIQUE_PROMPT.replace("{task}", task).replace("{answer}", answer).replace("{checks}", checks));
+        match critic.call_structured::<Critique>(vec![&prompt], &GenerationOptions::default()).await {
+            Ok(critique) => Ok((critique.passed, critique.critique)),
+            Err(e) => {
+                tracing::error!("Failed to review the answer: {}. Stopping the run.", e);
+                Err(format!("The critic failed to review the answer: {}", e))
+            }
+        }
+    }
+}
+
+
+#[async_trait]
+impl <A: BaseAgent + Send> BaseAgent for ReflectiveAgent<A> {
+    async fn add_message(&mut self, message: Message) {
+        self.inner.add_message(message).await;
+    }
+
+    fn build_messages(&self) -> MessageIter<'_> {
+        self.inner.build_messages()
+    }
+
+    fn clear_history(&mut self) {
+        self.inner.clear_history();
+        self.reflections.clear();
+    }
+
+    fn get_history(&self) -> MessageIter<'_> {
+        self.inner.get_history()
+    }
+
+    async fn run(&mut self, user_prompt: &str) -> String {
+        self.reflections.clear();
+        self.critic.take_usage();
+        let mut usage = Usage::default();
+        let mut answer = String::new();
+        for round in 1..=self.max_rounds {
+            let round_span = tracing::info_span!("reflection_round", r_agent.round = round);
+            answer = if round == 1 {
+                self.inner.run(user_prompt).instrument(round_span).await
+            } else {
+                self.inner.run(&RETRY_PROMPT.replace("{task}", user_prompt)).instrument(round_span).await
+            };
+            if let Some(round_usage) = self.inner.run_usage() {
+                usage.accumulate(&round_usage);
+            }
+            let review = match self.check(user_prompt, &answer) {
+                Ok(checks) => Self::critique(&self.critic, user_prompt, &answer, &checks).await,
+                Err(message) => Ok((false, message)),
+            };
+            let (passed, critique) = review.clone().unwrap_or_else(|error| (false, error));
+            tracing::info!("Round {} {}: {}", round, if passed { "passed" } else { "failed" }, critique);
+            self.reflections.push(Reflection { round, answer: answer.clone(), passed, critique: critique.clone() });
+            // without a working critic another round would go unreviewed too
+            if passed || review.is_err() {
+                break;
+            }
+            if round < self.max_rounds {
+                let reflection = REFLECTION_PROMPT.replace("{round}", &round.to_string()).replace("{critique}", &critique);
+                self.inner.add_message(Message::user(&reflection)).await;
+            }
+        }
+        usage.accumulate(&self.critic.take_usage());
+        self.last_usage = usage;
+        answer
+    }
+
+    fn run_usage(&self) -> Option<Usage> {
+        Some(self.last_usage.clone())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::sliding_window::SlidingWindowMemory, prompt::agent::REACT_END_TOKEN, test_support::*};
+
+    #[tokio::test]
+    async fn test_reflective_agent_retries_with_critique() {
+        let config = offline_config();
+        let inner = react_agent(&config, "You are a calculator.", SlidingWindowMemory::new(50, MODEL, 100000),
+                                ScriptedModel::new(["41", "43", "42"].map(|n| format!("{} {}", n, REACT_END_TOKEN))));
+        let critic = ScriptedModel::new([
+            r#"{"passed": false, "critique": "Off by one, recount."}"#,
+            r#"{"passed": true, "critique": ""}"#,
+        ]);
+        let mut agent = ReflectiveAgent::new(&config, MODEL, inner, 3)
+                            .map_critic(|_| Box::new(critic))
+                            .with_checker(|_, answer| if answer.parse::<u32>().unwrap() > 41 { Ok(()) } else { Err("Too small.".to_string()) });
+
+        let answer = agent.run("What is 6 * 7?").await;
+        assert_eq!(answer, "42");
+        let rounds: Vec<(bool, &str)> = agent.reflections().iter().map(|r| (r.passed, r.critique.as_str())).collect();
+        assert_eq!(rounds, vec![(false, "Too small."), (false, "Off by one, recount."), (true, "")]);
+        assert!(agent.get_history().any(|m| m.content.contains("Reflection:\nOff by one, recount.")));
+    }
+
+    #[tokio::test]
+    async fn test_reflective_agent_stops_when_critic_fails() {
+        let config = offline_config();
+        let inner = react_agent(&config, "You are a calculator.", SlidingWindowMemory::new(50, MODEL, 100000),
+                                ScriptedModel::new([format!("41 {}", REACT_END_TOKEN)]));
+        let mut agent = ReflectiveAgent::new(&config, MODEL, inner, 3)
+                            .map_critic(|_| Box::new(FnModel(|_: &[&Message]| text("not a review"))));
+
+        assert_eq!(agent.run("What is 6 * 7?").await, "41");
+        let reflections = agent.reflections();
+        assert_eq!(reflections.len(), 1);
+        assert!(!reflections[0].passed);
+        assert!(reflections[0].critique.starts_with("The critic failed to review the answer"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use crate::agent::base::BaseAgent;
+
+/// Agent registry
+/// Holds agents of any type by name, e.g. a `ReactAgent` next to a `ReflectiveAgent` wrapping another one.
+pub struct AgentRegistry {
+    agents: BTreeMap<String, Box<dyn BaseAgent + Send>>,
+}
+
+impl Default for AgentRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AgentRegistry {
+    pub fn new() -> Self {
+        AgentRegistry { agents: BTreeMap::new() }
+    }
+
+    /// Add an agent, replacing one with the same name
+    pub fn with_agent<A: BaseAgent + Send + 'static>(mut self, name: &str, agent: A) -> Self {
+        self.insert(name, Box::new(agent));
+        self
+    }
+
+    /// Add an agent, returns the one it replaces
+    pub fn insert(&mut self, name: &str, agent: Box<dyn BaseAgent + Send>) -> Option<Box<dyn BaseAgent + Send>> {
+        self.agents.insert(name.to_string(), agent)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn BaseAgent + Send>> {
+        self.agents.remove(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.agents.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&(dyn BaseAgent + Send)> {
+        self.agents.get(name).map(|agent| agent.as_ref())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Box<dyn BaseAgent + Send>> {
+        self.agents.get_mut(name)
+    }
+
+    /// Run the agent `name`, `None` if there is no such agent
+    pub async fn run(&mut self, name: &str, user_prompt: &str) -> Option<String> {
+        let agent = self.agents.get_mut(name)?;
+        Some(agent.run(user_prompt).await)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{agent::reflective_agent::ReflectiveAgent, config::config::Config,
+                memory::{registry::MemoryRegistry, sliding_window::SlidingWindowMemory}, test_support::*};
+
+    fn config_with_memory(memory_kind: &str) -> Config {
+        offline_config_with(&format!("memory:\n  kind: {}\n  max_tokens: 1000\n  workspace: ./workspace_test/registry", memory_kind))
+    }
+
+    #[tokio::test]
+    async fn test_registries() {
+        let _ = fs::remove_dir_all("./workspace_test/registry");
+        let registry = MemoryRegistry::default()
+                        .with_factory("tiny", |_, _, _| Box::new(SlidingWindowMemory::new(2, MODEL, 1000)));
+        assert_eq!(registry.kinds().collect::<Vec<_>>(), vec!["entity", "sliding_window", "summary", "tiny"]);
+        assert!(registry.from_config(&config_with_memory("vector"), "task").is_err());
+
+        // the memory is chosen by config, the agents differ in type but share one registry
+        let summary = registry.from_config(&config_with_memory("summary"), "task").unwrap();
+        let tiny = registry.from_config(&config_with_memory("tiny"), "task").unwrap();
+        let config = config_with_memory("summary");
+        let reflective = ReflectiveAgent::new(&config, MODEL, react_agent(&config, "You are a helpful assistant.", tiny, echo_model()), 1).map_critic(|_| Box::new(echo_model()));
+        let mut agents = AgentRegistry::new()
+                            .with_agent("assistant", react_agent(&config, "You are a helpful assistant.", summary, echo_model()))
+                            .with_agent("reflective", reflective);
+        assert_eq!(agents.names().collect::<Vec<_>>(), vec!["assistant", "reflective"]);
+
+        assert_eq!(agents.run("assistant", "hello").await.as_deref(), Some("echo hello"));
+        agents.run("reflective", "one").await;
+        agents.run("reflective", "two").await;
+        assert_eq!(agents.get("assistant").unwrap().get_history().count(), 2);
+        // the two message window of the tiny memory
+        assert_eq!(agents.get("reflective").unwrap().get_history().count(), 2);
+        assert!(agents.run("missing", "hello").await.is_none());
+        fs::remove_dir_all("./workspace_test/registry").unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::{fmt, sync::Arc};
+use serde_json::{json, Value};
+use crate::{model::schema::LLMResponse, prompt::agent::*};
+
+pub type TerminationPredicate = Arc<dyn Fn(&LLMResponse) -> Option<String> + Send + Sync>;
+
+/// How a `ReactAgent` recognizes the final answer, the system prompt tells the model accordingly
+#[derive(Clone, Default)]
+pub enum Termination {
+    /// The content contains `REACT_END_TOKEN`, the answer is the text before it
+    #[default]
+    EndToken,
+    /// A response without tool calls is the final answer
+    NoToolCalls,
+    /// The model calls the `finish` tool with `{"answer": "..."}`
+    FinishTool,
+    /// `predicate` returns the final answer, `instructions` replace the "When to Finish" section of the prompt
+    Custom { instructions: String, predicate: TerminationPredicate },
+}
+
+impl fmt::Debug for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::EndToken => write!(f, "EndToken"),
+            Termination::NoToolCalls => write!(f, "NoToolCalls"),
+            Termination::FinishTool => write!(f, "FinishTool"),
+            Termination::Custom { instructions, .. } => f.debug_struct("Custom").field("instructions", instructions).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Termination {
+    pub fn custom<F: Fn(&LLMResponse) -> Option<String> + Send + Sync + 'static>(instructions: &str, predicate: F) -> Self {
+        Termination::Custom { instructions: instructions.to_string(), predicate: Arc::new(predicate) }
+    }
+
+    /// The ReAct system prompt with the finish instructions of this strategy
+    pub fn system_prompt(&self) -> String {
+        match self {
+            Termination::EndToken => REACT_SYSTEM_PROMPT.clone(),
+            Termination::NoToolCalls => react_system_prompt(NO_TOOL_CALLS_FINISH_INSTRUCTIONS, NO_TOOL_CALLS_FINISH_RULES, ""),
+            Termination::FinishTool => react_system_prompt(FINISH_TOOL_FINISH_INSTRUCTIONS, FINISH_TOOL_FINISH_RULES, ""),
+            Termination::Custom { instructions, .. } => react_system_prompt(instructions, "", ""),
+        }
+    }
+
+    /// Tools the model needs for this strategy, the `finish` tool for `FinishTool`
+    pub fn tool_schemas(&self) -> Vec<Value> {
+        match self {
+            Termination::FinishTool => vec![json!({
+                "name": FINISH_TOOL,
+                "description": FINISH_TOOL_DESCRIPTION,
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "answer": { "type": "string", "description": "The complete final answer." }
+                    },
+                    "required": ["answer"]
+                }
+            })],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The final answer if `response` ends the run
+    pub fn final_answer(&self, response: &LLMResponse) -> Option<String> {
+        match self {
+            Termination::EndToken => {
+                let content = response.content.as_ref()?;
+                let end_index = content.find(REACT_END_TOKEN)?;
+                Some(content[..end_index].trim().to_string())
+            },
+            Termination::NoToolCalls => {
+                if response.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) {
+                    return None;
+                }
+                response.content.as_ref().map(|content| content.trim().to_string())
+            },
+            Termination::FinishTool => {
+                let call = response.tool_calls.as_ref()?.iter().find(|call| call.function.name == FINISH_TOOL)?;
+                let answer = serde_json::from_str::<Value>(&call.function.arguments).ok()
+                                .and_then(|args| args.get("answer").and_then(Value::as_str).map(String::from))
+                                .unwrap_or_else(|| call.function.arguments.clone());
+                Some(answer)
+            },
+            Termination::Custom { predicate, .. } => predicate(response),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llm::{FunctionCall, ToolCall};
+
+    fn response(content: Option<&str>, tool: Option<(&str, &str)>) -> LLMResponse {
+        LLMResponse {
+            content: content.map(String::from),
+            reasoning_content: None,
+            usage: None,
+            tool_calls: tool.map(|(name, arguments)| vec![ToolCall {
+                id: "call_0".to_string(),
+                call_type: "function".to_string(),
+                function: FunctionCall { name: name.to_string(), arguments: arguments.to_string() },
+            }]),
+        }
+    }
+
+    #[test]
+    fn test_final_answer_per_strategy() {
+        let ended = response(Some(&format!("42 {}", REACT_END_TOKEN)), None);
+        let plain = response(Some("42"), None);
+        let finish = response(None, Some((FINISH_TOOL, r#"{"answer": "42"}"#)));
+        let search = response(Some("let me look"), Some(("search", "{}")));
+
+        assert_eq!(Termination::EndToken.final_answer(&ended).as_deref(), Some("42"));
+        assert_eq!(Termination::EndToken.final_answer(&plain), None);
+        assert_eq!(Termination::NoToolCalls.final_answer(&plain).as_deref(), Some("42"));
+        assert_eq!(Termination::NoToolCalls.final_answer(&search), None);
+        assert_eq!(Termination::FinishTool.final_answer(&finish).as_deref(), Some("42"));
+        assert_eq!(Termination::FinishTool.final_answer(&plain), None);
+
+        let custom = Termination::custom("Say DONE when done.", |r| r.content.as_ref()?.strip_suffix("DONE").map(|a| a.trim().to_string()));
+        assert_eq!(custom.final_answer(&response(Some("42 DONE"), None)).as_deref(), Some("42"));
+    }
+
+    #[test]
+    fn test_system_prompt_follows_strategy() {
+        assert!(Termination::EndToken.system_prompt().contains(REACT_END_TOKEN));
+        assert!(!Termination::NoToolCalls.system_prompt().contains(REACT_END_TOKEN));
+        assert!(Termination::FinishTool.system_prompt().contains("`finish` tool"));
+        assert!(!Termination::FinishTool.system_prompt().contains(REACT_END_TOKEN));
+        assert!(Termination::custom("Say DONE when done.", |_| None).system_prompt().contains("Say DONE when done."));
+    }
+}
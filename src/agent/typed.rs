@@ -0,0 +1,88 @@
+use std::fmt;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use crate::{model::structured::parse_json, prompt::agent::*};
+
+/// A typed run ended without a valid answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedRunError {
+    /// `final_answer` was called, but its arguments were still invalid after all correction attempts
+    InvalidAnswer { attempts: usize, message: String },
+    /// The model never called `final_answer`
+    MaxIterations { iterations: usize },
+}
+
+impl fmt::Display for TypedRunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypedRunError::InvalidAnswer { attempts, message } => write!(f, "Final answer still invalid after {} attempt(s): {}", attempts, message),
+            TypedRunError::MaxIterations { iterations } => write!(f, "No final answer after {} iteration(s)", iterations),
+        }
+    }
+}
+
+impl std::error::Error for TypedRunError {}
+
+pub(crate) type AnswerValidator<'a> = &'a (dyn Fn(&str) -> Result<(), String> + Send + Sync);
+
+/// The `final_answer` tool offered to the model during a typed run
+pub(crate) struct AnswerTool<'a> {
+    pub schema: Value,
+    pub validate: AnswerValidator<'a>,
+    pub max_attempts: usize,
+}
+
+/// Function parameters must be an object, any other `T` is wrapped as `{"answer": T}`.
+/// Returns the parameters schema and whether it is wrapped.
+pub(crate) fn answer_schema<T: JsonSchema>() -> (Value, bool) {
+    let mut schema = schemars::schema_for!(T).to_value();
+    if let Some(obj) = schema.as_object_mut() {
+        obj.remove("$schema");
+    }
+    if schema.get("type") == Some(&json!("object")) {
+        return (schema, false);
+    }
+    let defs = schema.as_object_mut().and_then(|obj| obj.remove("$defs"));
+    let mut wrapper = json!({
+        "type": "object",
+        "properties": { "answer": schema },
+        "required": ["answer"],
+    });
+    if let Some(defs) = defs {
+        wrapper["$defs"] = defs;
+    }
+    (wrapper, true)
+}
+
+pub(crate) fn answer_tool_schema(parameters: Value) -> Value {
+    json!({
+        "name": FINAL_ANSWER_TOOL,
+        "description": FINAL_ANSWER_TOOL_DESCRIPTION,
+        "parameters": parameters,
+    })
+}
+
+pub(crate) fn parse_answer<T: DeserializeOwned>(arguments: &str, wrapped: bool) -> Result<T, String> {
+    if !wrapped {
+        return parse_json::<T>(arguments);
+    }
+    let mut value = parse_json::<Value>(arguments)?;
+    let answer = value.get_mut("answer").map(Value::take).ok_or("missing field `answer`")?;
+    serde_json::from_value(answer).map_err(|e| e.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_schema_wraps_non_objects() {
+        let (schema, wrapped) = answer_schema::<Vec<u32>>();
+        assert!(wrapped);
+        assert_eq!(schema["required"], json!(["answer"]));
+        assert_eq!(parse_answer::<Vec<u32>>(r#"{"answer": [1, 2]}"#, true).unwrap(), vec![1, 2]);
+        assert!(parse_answer::<Vec<u32>>(r#"{"answer": "x"}"#, true).is_err());
+    }
+}
@@ -1 +1,5 @@
-pub mod config;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod config;
+pub mod env;
+pub mod layers;
+pub mod validate;
\ No newline at end of file
@@ -1,11 +1,13 @@
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, fmt};
 use anyhow::Context;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use serde::Deserialize;
-use tracing_subscriber::{fmt, EnvFilter};
-use tracing_appender::{rolling, non_blocking::WorkerGuard};
-use once_cell::sync::OnceCell;
-static LOG_GUARD: OnceCell<WorkerGuard> = OnceCell::new();
+use llm::builder::LLMBackend;
+use crate::model::options::GenerationOptions;
+use crate::config::{env::{interpolate_env, process_env, EnvLookup}, layers::{ConfigLoader, ConfigSources}, validate::{validate, ConfigError, ConfigIssue}};
+
+/// Accepted values of `ModelConfig.provider`, `openai_compatible` talks the OpenAI protocol to `base_url`
+pub const PROVIDERS: [&str; 4] = ["openai", "openai_compatible", "anthropic", "deepseek"];
 
 pub fn get_config_file_path() -> PathBuf {
     env::var("R_AGENT_CONFIG_FILE")
@@ -19,20 +21,250 @@ pub fn get_config_file_path() -> PathBuf {
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
+    #[serde(default = "default_log_level")]
     pub log_level: String,
+    #[serde(default = "default_log_dir")]
     pub log_dir: String,
+    #[serde(default = "default_log_file")]
     pub log_file: String,
+    // where `telemetry::init_logging` sends the logs
+    #[serde(default)]
+    pub log_output: LogOutput,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    // only used by the rolling output
+    #[serde(default)]
+    pub log_rotation: LogRotation,
     pub models: HashMap<String, ModelConfig>,
     pub summary_model: String,
+    // the memory `MemoryRegistry::from_config` builds for an agent
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    // which layer each value came from, filled by `ConfigLoader`
+    #[serde(skip)]
+    pub sources: ConfigSources,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    None,
+    Stdout,
+    #[default]
+    File,
+    Rolling,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_dir() -> String {
+    "./logs".to_string()
+}
+
+fn default_log_file() -> String {
+    "r_agent.log".to_string()
+}
+
+#[derive(Deserialize, Clone)]
 pub struct ModelConfig {
+    // the key itself, or read it from `api_key_env` / `api_key_file` when empty
+    #[serde(default)]
     pub api_key: String,
+    pub api_key_env: Option<String>,
+    pub api_key_file: Option<String>,
+    // one of `PROVIDERS`, defaults to openai
+    pub provider: Option<String>,
     pub base_url: Option<String>,
     pub cost: Option<Cost>,
     pub temperature: Option<f32>,
     pub top_p: Option<f32>,
+    // defaults for every request, `temperature` and `top_p` above take precedence
+    pub generation: Option<GenerationOptions>,
+    pub cache: Option<CacheConfig>,
+}
+
+impl ModelConfig {
+    /// Fill `api_key` from the configured secret source if it is not given inline
+    pub fn resolve_api_key(&mut self) -> anyhow::Result<()> {
+        self.resolve_api_key_with(&process_env)
+    }
+
+    /// `resolve_api_key` reading `api_key_env` through `env`
+    pub fn resolve_api_key_with(&mut self, env: EnvLookup) -> anyhow::Result<()> {
+        if !self.api_key.is_empty() {
+            return Ok(());
+        }
+        if let Some(var) = self.api_key_env.as_deref() {
+            self.api_key = env(var).with_context(|| format!("Environment variable {} for api_key is not set", var))?;
+        } else if let Some(file) = self.api_key_file.as_deref() {
+            let key = std::fs::read_to_string(file).with_context(|| format!("Failed to read api_key_file: {}", file))?;
+            self.api_key = key.trim().to_string();
+        }
+        Ok(())
+    }
+
+    /// Config defaults for `GenerationOptions`, agents and calls layer their own options on top
+    pub fn generation_options(&self) -> GenerationOptions {
+        let mut options = self.generation.clone().unwrap_or_default();
+        options.temperature = self.temperature.or(options.temperature);
+        options.top_p = self.top_p.or(options.top_p);
+        if options.max_output_tokens.is_none() && let Some(cost) = self.cost.as_ref() && cost.max_output_tokens > 0 {
+            options.max_output_tokens = Some(cost.max_output_tokens as u32);
+        }
+        options
+    }
+
+    /// The llm backend for `provider`, unknown providers are rejected by `validate`
+    pub fn backend(&self) -> LLMBackend {
+        match self.provider.as_deref() {
+            Some("anthropic") => LLMBackend::Anthropic,
+            Some("deepseek") => LLMBackend::DeepSeek,
+            _ => LLMBackend::OpenAI,
+        }
+    }
+
+    /// The OpenTelemetry `gen_ai.system` of `backend()`, `openai_compatible` servers speak the openai protocol
+    pub fn gen_ai_system(&self) -> &'static str {
+        match self.backend() {
+            LLMBackend::Anthropic => "anthropic",
+            LLMBackend::DeepSeek => "deepseek",
+            LLMBackend::OpenAI => "openai",
+            _ => "_OTHER",
+        }
+    }
+}
+
+// never print the api key, in tests or in logs
+impl fmt::Debug for ModelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModelConfig")
+            .field("api_key", &redact(&self.api_key))
+            .field("api_key_env", &self.api_key_env)
+            .field("api_key_file", &self.api_key_file)
+            .field("provider", &self.provider)
+            .field("base_url", &self.base_url)
+            .field("cost", &self.cost)
+            .field("temperature", &self.temperature)
+            .field("top_p", &self.top_p)
+            .field("generation", &self.generation)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+fn redact(secret: &str) -> &'static str {
+    if secret.is_empty() { "" } else { "<redacted>" }
+}
+
+/// Which memory agents get, see `MemoryRegistry`
+#[derive(Deserialize, Debug, Clone)]
+pub struct MemoryConfig {
+    // a kind registered in the `MemoryRegistry`, built in are sliding_window, summary and entity
+    #[serde(default = "default_memory_kind")]
+    pub kind: String,
+    // tokenizer and summary/extraction model, defaults to `summary_model`
+    pub model: Option<String>,
+    // defaults to `cost.max_input_tokens` of the model, or 8192
+    pub max_tokens: Option<usize>,
+    // only used by sliding_window
+    #[serde(default = "default_memory_max_messages")]
+    pub max_messages: usize,
+    // share of max_tokens kept for the summary, only used by summary
+    #[serde(default = "default_memory_reserve_ratio")]
+    pub reserve_ratio: f32,
+    // messages sent to the model in one extraction call, 0 turns extraction off, only used by entity
+    #[serde(default = "default_memory_extraction_batch")]
+    pub extraction_batch: usize,
+    // the persistent memories keep one directory per task here
+    #[serde(default = "default_memory_workspace")]
+    pub workspace: String,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig {
+            kind: default_memory_kind(),
+            model: None,
+            max_tokens: None,
+            max_messages: default_memory_max_messages(),
+            reserve_ratio: default_memory_reserve_ratio(),
+            extraction_batch: default_memory_extraction_batch(),
+            workspace: default_memory_workspace(),
+        }
+    }
+}
+
+impl MemoryConfig {
+    pub fn model_name<'a>(&'a self, config: &'a Config) -> &'a str {
+        self.model.as_deref().unwrap_or(&config.summary_model)
+    }
+
+    pub fn token_budget(&self, config: &Config) -> usize {
+        self.max_tokens
+            .or_else(|| config.models.get(self.model_name(config)).and_then(|m| m.cost.as_ref()).map(|cost| cost.max_input_tokens))
+            .unwrap_or(8192)
+    }
+}
+
+fn default_memory_kind() -> String {
+    "sliding_window".to_string()
+}
+
+fn default_memory_max_messages() -> usize {
+    50
+}
+
+fn default_memory_reserve_ratio() -> f32 {
+    0.3
+}
+
+pub(crate) fn default_memory_extraction_batch() -> usize {
+    4
+}
+
+fn default_memory_workspace() -> String {
+    "./workspace".to_string()
+}
+
+/// Response cache for identical requests, see `CachedModel`
+#[derive(Deserialize, Debug, Clone)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_enabled")]
+    pub enabled: bool,
+    // entries older than this are ignored, no expiry when unset
+    pub ttl_secs: Option<u64>,
+    // max number of entries kept in memory
+    #[serde(default = "default_cache_capacity")]
+    pub capacity: usize,
+    // also persist entries as json files under this directory
+    pub dir: Option<String>,
+}
+
+fn default_cache_enabled() -> bool {
+    true
+}
+
+fn default_cache_capacity() -> usize {
+    256
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -44,41 +276,128 @@ pub struct Cost{
     pub max_output_tokens: usize,
 }
 
+/// Parse the config file, logging is not touched, see `telemetry::init_logging`.
+/// Panics with every problem found in the file, use `try_load_config` to handle them.
 pub fn load_config(file: Option<&str>) -> Config {
-    let config_path = match file {
-        Some(path) => PathBuf::from(path),
-        None => get_config_file_path(),
+    try_load_config(file).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Read, merge, interpolate, parse and validate the config.
+/// The file is layered with the `R_AGENT_PROFILE` profile file and `R_AGENT__*` environment variables, see `ConfigLoader`.
+pub fn try_load_config(file: Option<&str>) -> Result<Config, ConfigError> {
+    ConfigLoader::new(file).load()
+}
+
+/// The profile file sits next to the base file, `config.yaml` with profile `dev` is `config.dev.yaml`
+pub fn get_profile_file_path(base: &Path, profile: &str) -> PathBuf {
+    let stem = base.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_name = match base.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, profile, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, profile),
     };
+    base.with_file_name(file_name)
+}
 
-    let config_content = std::fs::read_to_string(&config_path)
-                                .with_context(|| format!("Failed to read config file: {}", config_path.display()))
-                                .unwrap();
-    let cfg: Config = serde_yml::from_str(&config_content)
-                                .with_context(|| format!("Failed to parse config file: {}", config_path.display()))
-                                .unwrap();
-    // init the logging
-    LOG_GUARD.get_or_init(|| 
-        {
-            let appender = rolling::never(cfg.log_dir.clone(), cfg.log_file.clone());
-            let (writer, guard) = tracing_appender::non_blocking(appender);
-            let filter = EnvFilter::new(cfg.log_level.clone());
-            let _ = fmt()
-                        .with_env_filter(filter)
-                        .with_writer(writer)
-                        .with_ansi(false)
-                        .init();
-            guard
-        });
-    // return the config
-    cfg
+/// Interpolate and parse the merged layers, then resolve the api keys and validate the result
+pub(crate) fn build_config(config_path: PathBuf, mut value: serde_yml::Value, sources: ConfigSources, env: EnvLookup) -> Result<Config, ConfigError> {
+    interpolate_env(&mut value, env)
+                                .map_err(|issues| ConfigError::Invalid { file: config_path.clone(), issues })?;
+    let mut cfg: Config = serde_path_to_error::deserialize(value)
+                                .map_err(|e| ConfigError::Parse { file: config_path.clone(), issue: ConfigIssue::new(e.path().to_string(), e.inner().to_string()) })?;
+    cfg.sources = sources;
+
+    let mut key_issues = Vec::new();
+    for (name, model) in cfg.models.iter_mut() {
+        if let Err(e) = model.resolve_api_key_with(env) {
+            let field = if model.api_key_env.is_some() { "api_key_env" } else { "api_key_file" };
+            key_issues.push(ConfigIssue::new(format!("models.{}.{}", name, field), format!("{:#}", e)));
+        }
+    }
+    key_issues.sort_by(|a, b| a.path.cmp(&b.path));
+    // an unresolved key source already explains why the key is empty
+    let mut issues: Vec<ConfigIssue> = validate(&cfg).into_iter()
+        .filter(|issue| !key_issues.iter().any(|k| issue.path.strip_suffix(".api_key").is_some_and(|model| k.path.starts_with(&format!("{}.", model)))))
+        .collect();
+    issues.extend(key_issues);
+    if !issues.is_empty() {
+        return Err(ConfigError::Invalid { file: config_path, issues });
+    }
+    Ok(cfg)
 }
 
 mod tests {
+    #[test]
+    fn test_api_key_sources_and_redaction() {
+        let dir = "./workspace_test/config_api_key";
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(format!("{}/key.txt", dir), "sk-from-file\n").unwrap();
+        let mut models: std::collections::HashMap<String, super::ModelConfig> = serde_yml::from_str(&format!(r#"
+inline:
+  api_key: sk-inline
+from_env:
+  api_key_env: API_KEY
+from_file:
+  api_key_file: {}/key.txt
+"#, dir)).unwrap();
+        for model in models.values_mut() {
+            model.resolve_api_key_with(&|name| (name == "API_KEY").then(|| "sk-from-env".to_string())).unwrap();
+        }
+        assert_eq!(models["inline"].api_key, "sk-inline");
+        assert_eq!(models["from_env"].api_key, "sk-from-env");
+        assert_eq!(models["from_file"].api_key, "sk-from-file");
+        let printed = format!("{:?}", models);
+        assert!(!printed.contains("sk-"));
+        assert!(printed.contains("<redacted>"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_try_load_config_reports_paths() {
+        let dir = "./workspace_test/config_invalid";
+        std::fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/config.yaml", dir);
+        std::fs::write(&path, r#"
+summary_model: gpt-4o-mini
+log_level: info
+models:
+  gpt-4o-mini:
+    api_key_env: R_AGENT_TEST_SURELY_UNSET_KEY
+    provider: mistral
+"#).unwrap();
+        let err = super::try_load_config(Some(&path)).unwrap_err();
+        let paths: Vec<String> = err.issues().into_iter().map(|issue| issue.path).collect();
+        assert_eq!(paths, vec!["models.gpt-4o-mini.provider", "models.gpt-4o-mini.api_key_env"]);
+
+        std::fs::write(&path, "summary_model: a\nmodels:\n  a:\n    temperature: hot\n").unwrap();
+        let err = super::try_load_config(Some(&path)).unwrap_err();
+        assert!(matches!(err, super::ConfigError::Parse { .. }));
+        assert_eq!(err.issues()[0].path, "models.a.temperature");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_gen_ai_system() {
+        let models: std::collections::HashMap<String, super::ModelConfig> = serde_yml::from_str(r#"
+default: {}
+compatible: { provider: openai_compatible }
+claude: { provider: anthropic }
+"#).unwrap();
+        assert_eq!(models["default"].gen_ai_system(), "openai");
+        assert_eq!(models["compatible"].gen_ai_system(), "openai");
+        assert_eq!(models["claude"].gen_ai_system(), "anthropic");
+    }
+
+    #[test]
+    fn test_example_config_loads() {
+        let cfg = super::ConfigLoader::new(Some("example.yaml")).with_env(std::iter::empty::<(String, String)>()).load().unwrap();
+        assert_eq!(cfg.models.len(), 3);
+    }
+
     #[test]
     fn test_load_config() {
         let config = super::load_config(None);
         println!("{:?}", config);
         tracing::debug!("Config loaded successfully: {:?}", config);
-        assert!(config.models.len() > 0);
+        assert!(!config.models.is_empty());
     }
 }
\ No newline at end of file
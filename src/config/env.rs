@@ -0,0 +1,122 @@
+use std::env;
+use serde_yml::Value;
+use crate::config::validate::ConfigIssue;
+
+/// Looks up an environment variable, `None` when it is not set
+pub type EnvLookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// `EnvLookup` of the process environment
+pub fn process_env(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` in every string of the config with the variable from `env`.
+/// Like in the shell the default also replaces an empty variable, `${VAR}` keeps it empty.
+/// `$${` is kept as a literal `${`. Unset variables without a default are reported with their path in the config.
+pub fn interpolate_env(value: &mut Value, env: EnvLookup) -> Result<(), Vec<ConfigIssue>> {
+    let mut missing = Vec::new();
+    interpolate_value(value, "", env, &mut missing);
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+    Ok(())
+}
+
+fn interpolate_value(value: &mut Value, path: &str, env: EnvLookup, missing: &mut Vec<ConfigIssue>) {
+    match value {
+        Value::String(s) if s.contains('$') => {
+            *s = interpolate_str(s, path, env, missing);
+        },
+        Value::Sequence(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{}[{}]", path, i), env, missing);
+            }
+        },
+        Value::Mapping(map) => {
+            for (key, item) in map.iter_mut() {
+                let key = key.as_str().map(String::from).unwrap_or_else(|| format!("{:?}", key));
+                let child = if path.is_empty() { key } else { format!("{}.{}", path, key) };
+                interpolate_value(item, &child, env, missing);
+            }
+        },
+        Value::Tagged(tagged) => interpolate_value(&mut tagged.value, path, env, missing),
+        _ => {},
+    }
+}
+
+fn interpolate_str(s: &str, path: &str, env: EnvLookup, missing: &mut Vec<ConfigIssue>) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(escaped) = tail.strip_prefix("$${") {
+            out.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        let Some(end) = tail.starts_with("${").then(|| tail.find('}')).flatten() else {
+            out.push('$');
+            rest = &tail[1..];
+            continue;
+        };
+        let expr = &tail[2..end];
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+        let value = match (env(name), default) {
+            (Some(v), Some(default)) if v.is_empty() => Some(default.to_string()),
+            (None, default) => default.map(String::from),
+            (v, _) => v,
+        };
+        match value {
+            Some(v) => out.push_str(&v),
+            None => missing.push(ConfigIssue::new(path, format!("environment variable {} is not set", name))),
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "KEY" => Some("sk-from-env".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let mut value: Value = serde_yml::from_str(r#"
+models:
+  gpt-4o-mini:
+    api_key: ${KEY}
+    base_url: https://${HOST:-api.openai.com}/v1
+    organization: ${EMPTY}
+    project: ${EMPTY:-default}
+    note: costs $$5, literal $${NOT_A_VAR}
+"#).unwrap();
+        interpolate_env(&mut value, &lookup).unwrap();
+        let model = &value["models"]["gpt-4o-mini"];
+        assert_eq!(model["api_key"].as_str(), Some("sk-from-env"));
+        assert_eq!(model["base_url"].as_str(), Some("https://api.openai.com/v1"));
+        assert_eq!(model["organization"].as_str(), Some(""));
+        assert_eq!(model["project"].as_str(), Some("default"));
+        assert_eq!(model["note"].as_str(), Some("costs $$5, literal ${NOT_A_VAR}"));
+    }
+
+    #[test]
+    fn test_interpolate_missing_env() {
+        let mut value: Value = serde_yml::from_str("models:\n  a:\n    api_key: ${UNSET}").unwrap();
+        let issues = interpolate_env(&mut value, &lookup).unwrap_err();
+        assert_eq!(issues, vec![ConfigIssue::new("models.a.api_key", "environment variable UNSET is not set")]);
+    }
+}
@@ -0,0 +1,340 @@
+use std::{collections::{BTreeMap, HashMap}, env, fmt, path::PathBuf};
+use serde_yml::{Mapping, Value};
+use crate::config::{config::{build_config, get_config_file_path, get_profile_file_path, Config}, validate::{ConfigError, ConfigIssue}};
+
+/// Environment variable selecting the profile used by `load_config`
+pub const PROFILE_ENV: &str = "R_AGENT_PROFILE";
+/// Prefix of the environment variables overriding single values, e.g. `R_AGENT__MODELS__GPT_4O_MINI__TEMPERATURE`
+pub const ENV_PREFIX: &str = "R_AGENT";
+
+/// Where an effective config value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    File(PathBuf),
+    Profile { name: String, file: PathBuf },
+    Env(String),
+    Override,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::File(file) => write!(f, "file {}", file.display()),
+            ConfigSource::Profile { name, file } => write!(f, "profile {} ({})", name, file.display()),
+            ConfigSource::Env(var) => write!(f, "env {}", var),
+            ConfigSource::Override => write!(f, "override"),
+        }
+    }
+}
+
+/// The source of every value set by a layer, keyed by dotted path like `models.gpt-4o-mini.temperature`.
+/// Paths that are missing fall back to the built-in defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigSources(BTreeMap<String, ConfigSource>);
+
+impl ConfigSources {
+    pub fn get(&self, path: &str) -> Option<&ConfigSource> {
+        self.0.get(path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ConfigSource)> {
+        self.0.iter()
+    }
+
+    fn record(&mut self, path: &str, value: &Value, source: &ConfigSource) {
+        match value {
+            Value::Mapping(map) if !map.is_empty() => {
+                for (key, item) in map {
+                    self.record(&child_path(path, &key_str(key)), item, source);
+                }
+            },
+            _ => { self.0.insert(path.to_string(), source.clone()); },
+        }
+    }
+
+    fn remove_under(&mut self, path: &str) {
+        let prefix = format!("{}.", path);
+        self.0.retain(|p, _| !path.is_empty() && p != path && !p.starts_with(&prefix));
+    }
+}
+
+/// Layered config loader, later layers win:
+/// base file, profile file (`config.<profile>.yaml`), `R_AGENT__*` environment variables, programmatic overrides.
+pub struct ConfigLoader {
+    file: PathBuf,
+    profile: Option<String>,
+    env_prefix: Option<String>,
+    // the variables for the profile, the overrides, `${VAR}` and `api_key_env`
+    env: HashMap<String, String>,
+    overrides: Vec<(String, Value)>,
+}
+
+impl ConfigLoader {
+    /// Starts from `file` or `get_config_file_path()`, with the process environment and the `R_AGENT` env prefix
+    pub fn new(file: Option<&str>) -> Self {
+        ConfigLoader {
+            file: file.map(PathBuf::from).unwrap_or_else(get_config_file_path),
+            profile: None,
+            env_prefix: Some(ENV_PREFIX.to_string()),
+            env: env::vars().collect(),
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Read the environment from `vars` instead of the process, e.g. in tests
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Self {
+        self.env = vars.into_iter().map(|(name, value)| (name.into(), value.into())).collect();
+        self
+    }
+
+    /// Use `profile` instead of the one in `R_AGENT_PROFILE`
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    /// Read overrides from `<prefix>__SECTION__KEY` variables instead of `R_AGENT__...`
+    pub fn env_prefix(mut self, prefix: &str) -> Self {
+        self.env_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Ignore environment variable overrides
+    pub fn without_env(mut self) -> Self {
+        self.env_prefix = None;
+        self
+    }
+
+    /// Override a single value, `path` is dotted like `models.gemini-2.5-flash.temperature`
+    pub fn set(mut self, path: &str, value: impl Into<Value>) -> Self {
+        self.overrides.push((path.to_string(), value.into()));
+        self
+    }
+
+    pub fn load(self) -> Result<Config, ConfigError> {
+        let mut root = Value::Mapping(Mapping::new());
+        let mut sources = ConfigSources::default();
+        // env values are strings, the keys of those a typed field may need as a number or bool
+        let mut env_values = HashMap::new();
+
+        let base = read_layer(&self.file)?;
+        merge(&mut root, base, "", &ConfigSource::File(self.file.clone()), &mut sources);
+
+        let profile = self.profile.clone().or_else(|| self.env.get(PROFILE_ENV).filter(|p| !p.is_empty()).cloned());
+        if let Some(profile) = profile.as_deref() {
+            let file = get_profile_file_path(&self.file, profile);
+            let layer = read_layer(&file)?;
+            merge(&mut root, layer, "", &ConfigSource::Profile { name: profile.to_string(), file }, &mut sources);
+        }
+
+        if let Some(prefix) = self.env_prefix.as_deref() {
+            let marker = format!("{}__", prefix);
+            let mut vars: Vec<(&String, &String)> = self.env.iter().filter(|(name, _)| name.starts_with(&marker)).collect();
+            vars.sort();
+            for (name, raw) in vars {
+                let segments: Vec<&str> = name[marker.len()..].split("__").filter(|s| !s.is_empty()).collect();
+                if segments.is_empty() {
+                    continue;
+                }
+                let keys = env_keys(&root, &segments);
+                set_path(&mut root, &keys, Value::String(raw.clone()), &ConfigSource::Env(name.clone()), &mut sources);
+                env_values.insert(keys.join("."), keys);
+            }
+        }
+
+        for (path, value) in &self.overrides {
+            let keys = dotted_keys(&root, path);
+            set_path(&mut root, &keys, value.clone(), &ConfigSource::Override, &mut sources);
+        }
+
+        // a string env value only becomes a scalar when its field rejects the string
+        let lookup = |name: &str| self.env.get(name).cloned();
+        loop {
+            match build_config(self.file.clone(), root.clone(), sources.clone(), &lookup) {
+                Err(ConfigError::Parse { issue, .. }) if let Some(keys) = env_values.remove(&issue.path)
+                                                       && let Some(value) = value_at_mut(&mut root, &keys)
+                                                       && let Some(raw) = value.as_str() => {
+                    *value = parse_scalar(raw);
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+fn read_layer(file: &PathBuf) -> Result<Value, ConfigError> {
+    let content = std::fs::read_to_string(file)
+                        .map_err(|e| ConfigError::Io { file: file.clone(), message: e.to_string() })?;
+    let layer: Value = serde_yml::from_str(&content)
+                        .map_err(|e| ConfigError::Parse { file: file.clone(), issue: ConfigIssue::new("", e.to_string()) })?;
+    // an empty file parses to null, which would replace everything below it
+    Ok(if layer.is_null() { Value::Mapping(Mapping::new()) } else { layer })
+}
+
+/// Mappings are merged key by key, anything else replaces the current value
+fn merge(base: &mut Value, layer: Value, path: &str, source: &ConfigSource, sources: &mut ConfigSources) {
+    match (base, layer) {
+        (Value::Mapping(base_map), Value::Mapping(layer_map)) => {
+            for (key, item) in layer_map {
+                let child = child_path(path, &key_str(&key));
+                match base_map.get_mut(&key) {
+                    Some(existing) => merge(existing, item, &child, source, sources),
+                    None => {
+                        sources.record(&child, &item, source);
+                        base_map.insert(key, item);
+                    }
+                }
+            }
+        },
+        (base, layer) => {
+            sources.remove_under(path);
+            sources.record(path, &layer, source);
+            *base = layer;
+        }
+    }
+}
+
+fn set_path(root: &mut Value, keys: &[String], value: Value, source: &ConfigSource, sources: &mut ConfigSources) {
+    let mut layer = value;
+    for key in keys.iter().rev() {
+        let mut map = Mapping::new();
+        map.insert(Value::String(key.clone()), layer);
+        layer = Value::Mapping(map);
+    }
+    merge(root, layer, "", source, sources);
+}
+
+/// Env segments are matched against existing keys with everything but letters and digits as `_`,
+/// so `GPT_4O_MINI` finds `gpt-4o-mini`. Unknown keys are lowercased.
+fn env_keys(root: &Value, segments: &[&str]) -> Vec<String> {
+    let mut current = Some(root);
+    let mut keys = Vec::new();
+    for segment in segments {
+        let existing = current.and_then(Value::as_mapping).and_then(|map| {
+            map.keys().map(key_str).find(|key| normalize_env(key) == *segment)
+        });
+        let key = existing.unwrap_or_else(|| segment.to_lowercase());
+        current = current.and_then(|value| value.get(&key));
+        keys.push(key);
+    }
+    keys
+}
+
+fn normalize_env(key: &str) -> String {
+    key.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect()
+}
+
+/// Model names may contain dots, so the longest run of segments naming an existing key wins
+fn dotted_keys(root: &Value, path: &str) -> Vec<String> {
+    let parts: Vec<&str> = path.split('.').collect();
+    let mut current = Some(root);
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < parts.len() {
+        let map = current.and_then(Value::as_mapping);
+        let end = (i + 1..=parts.len()).rev()
+                        .find(|&end| map.is_some_and(|map| map.contains_key(parts[i..end].join("."))))
+                        .unwrap_or(i + 1);
+        let key = parts[i..end].join(".");
+        current = current.and_then(|value| value.get(&key));
+        keys.push(key);
+        i = end;
+    }
+    keys
+}
+
+/// An env value as a YAML scalar, so `0.2` is a number and `true` a bool, anything unparsable stays a string
+fn parse_scalar(raw: &str) -> Value {
+    match serde_yml::from_str::<Value>(raw) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_) | Value::String(_))) => value,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+fn value_at_mut<'a>(root: &'a mut Value, keys: &[String]) -> Option<&'a mut Value> {
+    keys.iter().try_fold(root, |value, key| value.get_mut(key.as_str()))
+}
+
+fn key_str(key: &Value) -> String {
+    key.as_str().map(String::from).unwrap_or_else(|| format!("{:?}", key))
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_layers_and_sources() {
+        let dir = "./workspace_test/config_layers";
+        fs::create_dir_all(dir).unwrap();
+        let base = format!("{}/config.yaml", dir);
+        fs::write(&base, r#"
+summary_model: gpt-4o-mini
+log_output: none
+models:
+  gpt-4o-mini:
+    api_key: sk-base
+    temperature: 0.7
+  gemini-2.5-flash:
+    provider: openai_compatible
+    base_url: https://example.com/v1/
+    api_key: sk-gemini
+"#).unwrap();
+        fs::write(format!("{}/config.ci.yaml", dir), "log_level: debug\nmodels:\n  gpt-4o-mini:\n    api_key: sk-ci\n    top_p: 0.5\n").unwrap();
+        let env = [
+            ("R_AGENT__MODELS__GPT_4O_MINI__TEMPERATURE", "0.2"),
+            ("R_AGENT__LOG_FORMAT", "json"),
+        ];
+
+        let cfg = ConfigLoader::new(Some(&base))
+                        .profile("ci")
+                        .with_env(env)
+                        .set("models.gemini-2.5-flash.temperature", 0.1)
+                        .load()
+                        .unwrap();
+        let mini = &cfg.models["gpt-4o-mini"];
+        assert_eq!(mini.api_key, "sk-ci");
+        assert_eq!(mini.temperature, Some(0.2));
+        assert_eq!(mini.top_p, Some(0.5));
+        assert_eq!(cfg.models["gemini-2.5-flash"].temperature, Some(0.1));
+        assert_eq!(cfg.log_level, "debug");
+
+        let profile = ConfigSource::Profile { name: "ci".to_string(), file: PathBuf::from(dir).join("config.ci.yaml") };
+        assert_eq!(cfg.sources.get("summary_model"), Some(&ConfigSource::File(PathBuf::from(&base))));
+        assert_eq!(cfg.sources.get("models.gpt-4o-mini.api_key"), Some(&profile));
+        assert_eq!(cfg.sources.get("models.gpt-4o-mini.temperature"), Some(&ConfigSource::Env("R_AGENT__MODELS__GPT_4O_MINI__TEMPERATURE".to_string())));
+        assert_eq!(cfg.sources.get("log_format"), Some(&ConfigSource::Env("R_AGENT__LOG_FORMAT".to_string())));
+        assert_eq!(cfg.sources.get("models.gemini-2.5-flash.temperature"), Some(&ConfigSource::Override));
+        assert_eq!(cfg.sources.get("log_rotation"), None);
+
+        // the profile also comes from the environment
+        let err = ConfigLoader::new(Some(&base)).with_env([(PROFILE_ENV, "missing")]).without_env().load().unwrap_err();
+        assert!(matches!(err, ConfigError::Io { .. }));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_empty_profile_and_string_env_values() {
+        let dir = "./workspace_test/config_layers_env";
+        fs::create_dir_all(dir).unwrap();
+        let base = format!("{}/config.yaml", dir);
+        fs::write(&base, "summary_model: gpt-4o-mini\nlog_output: none\nmodels:\n  gpt-4o-mini:\n    api_key: sk-base\n").unwrap();
+        fs::write(format!("{}/config.empty.yaml", dir), "").unwrap();
+        let env = [
+            ("R_AGENT__MODELS__GPT_4O_MINI__API_KEY", "12345"),
+            ("R_AGENT__MODELS__GPT_4O_MINI__TEMPERATURE", "0.5"),
+        ];
+
+        let cfg = ConfigLoader::new(Some(&base)).profile("empty").with_env(env).load().unwrap();
+        assert_eq!(cfg.summary_model, "gpt-4o-mini");
+        assert_eq!(cfg.models["gpt-4o-mini"].api_key, "12345");
+        assert_eq!(cfg.models["gpt-4o-mini"].temperature, Some(0.5));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::{fmt, path::PathBuf};
+use tracing_subscriber::EnvFilter;
+use crate::config::config::{Config, ModelConfig, PROVIDERS};
+
+/// A single problem in the config, `path` points at the offending YAML key, e.g. `models.gpt-4o-mini.api_key`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    pub path: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigIssue { path: path.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io { file: PathBuf, message: String },
+    /// The file is not valid YAML or does not match the config structure
+    Parse { file: PathBuf, issue: ConfigIssue },
+    /// The file parsed but has semantic problems, all of them are reported
+    Invalid { file: PathBuf, issues: Vec<ConfigIssue> },
+}
+
+impl ConfigError {
+    pub fn issues(&self) -> Vec<ConfigIssue> {
+        match self {
+            ConfigError::Io { message, .. } => vec![ConfigIssue::new("", message.clone())],
+            ConfigError::Parse { issue, .. } => vec![issue.clone()],
+            ConfigError::Invalid { issues, .. } => issues.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { file, message } => write!(f, "Failed to read config file {}: {}", file.display(), message),
+            ConfigError::Parse { file, issue } => write!(f, "Failed to parse config file {}: {}", file.display(), issue),
+            ConfigError::Invalid { file, issues } => {
+                write!(f, "Config file {} has {} problem(s):", file.display(), issues.len())?;
+                for issue in issues {
+                    write!(f, "\n  - {}", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Check the semantic rules that serde cannot express, every problem found is returned
+pub fn validate(cfg: &Config) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    if EnvFilter::try_new(&cfg.log_level).is_err() {
+        issues.push(ConfigIssue::new("log_level", format!("'{}' is not a valid log filter", cfg.log_level)));
+    }
+    if cfg.models.is_empty() {
+        issues.push(ConfigIssue::new("models", "at least one model must be configured"));
+    }
+    if !cfg.models.contains_key(&cfg.summary_model) {
+        issues.push(ConfigIssue::new("summary_model", format!("'{}' is not one of the configured models", cfg.summary_model)));
+    }
+    if let Some(model) = cfg.memory.model.as_ref() && !cfg.models.contains_key(model) {
+        issues.push(ConfigIssue::new("memory.model", format!("'{}' is not one of the configured models", model)));
+    }
+    if cfg.memory.max_tokens == Some(0) {
+        issues.push(ConfigIssue::new("memory.max_tokens", "must be greater than 0"));
+    }
+    if !(0.0..1.0).contains(&cfg.memory.reserve_ratio) {
+        issues.push(ConfigIssue::new("memory.reserve_ratio", format!("{} is outside of [0, 1)", cfg.memory.reserve_ratio)));
+    }
+    let mut names: Vec<&String> = cfg.models.keys().collect();
+    names.sort();
+    for name in names {
+        validate_model(&format!("models.{}", name), &cfg.models[name], &mut issues);
+    }
+    issues
+}
+
+fn validate_model(path: &str, model: &ModelConfig, issues: &mut Vec<ConfigIssue>) {
+    if model.api_key_env.is_some() && model.api_key_file.is_some() {
+        issues.push(ConfigIssue::new(format!("{}.api_key_env", path), "only one of api_key_env and api_key_file may be set"));
+    }
+    if model.api_key.trim().is_empty() {
+        issues.push(ConfigIssue::new(format!("{}.api_key", path), "api key is empty, set api_key, api_key_env or api_key_file"));
+    }
+
+    let provider = model.provider.as_deref().unwrap_or("openai");
+    if !PROVIDERS.contains(&provider) {
+        issues.push(ConfigIssue::new(format!("{}.provider", path), format!("unknown provider '{}', expected one of {}", provider, PROVIDERS.join(", "))));
+    }
+    match model.base_url.as_deref() {
+        Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => {
+            issues.push(ConfigIssue::new(format!("{}.base_url", path), format!("'{}' is not an http(s) url", url)));
+        },
+        None if provider == "openai_compatible" => {
+            issues.push(ConfigIssue::new(format!("{}.base_url", path), "base_url is required for provider openai_compatible"));
+        },
+        _ => {},
+    }
+
+    validate_sampling(path, model.temperature, model.top_p, issues);
+    if let Some(generation) = model.generation.as_ref() {
+        let path = format!("{}.generation", path);
+        validate_sampling(&path, generation.temperature, generation.top_p, issues);
+        if generation.max_output_tokens == Some(0) {
+            issues.push(ConfigIssue::new(format!("{}.max_output_tokens", path), "must be greater than 0"));
+        }
+    }
+
+    if let Some(cost) = model.cost.as_ref() {
+        if cost.input_cost_per_token < 0.0 {
+            issues.push(ConfigIssue::new(format!("{}.cost.input_cost_per_token", path), "must not be negative"));
+        }
+        if cost.output_cost_per_token < 0.0 {
+            issues.push(ConfigIssue::new(format!("{}.cost.output_cost_per_token", path), "must not be negative"));
+        }
+        if cost.max_output_tokens > cost.max_tokens {
+            issues.push(ConfigIssue::new(format!("{}.cost.max_output_tokens", path), format!("{} is larger than max_tokens ({})", cost.max_output_tokens, cost.max_tokens)));
+        }
+        if cost.max_tokens == 0 {
+            issues.push(ConfigIssue::new(format!("{}.cost.max_tokens", path), "must be greater than 0"));
+        }
+    }
+
+    if let Some(cache) = model.cache.as_ref() && cache.capacity == 0 {
+        issues.push(ConfigIssue::new(format!("{}.cache.capacity", path), "must be greater than 0"));
+    }
+}
+
+fn validate_sampling(path: &str, temperature: Option<f32>, top_p: Option<f32>, issues: &mut Vec<ConfigIssue>) {
+    if let Some(temperature) = temperature && !(0.0..=2.0).contains(&temperature) {
+        issues.push(ConfigIssue::new(format!("{}.temperature", path), format!("{} is outside of [0, 2]", temperature)));
+    }
+    if let Some(top_p) = top_p && !(0.0..=1.0).contains(&top_p) {
+        issues.push(ConfigIssue::new(format!("{}.top_p", path), format!("{} is outside of [0, 1]", top_p)));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_collects_all_issues() {
+        let cfg: Config = serde_yml::from_str(r#"
+summary_model: gpt-4o
+models:
+  gpt-4o-mini:
+    api_key: sk-xxx
+    cost:
+      input_cost_per_token: 0.0000003
+      output_cost_per_token: 0.00000252
+      max_tokens: 8192
+      max_input_tokens: 1048576
+      max_output_tokens: 16384
+  gemini-2.5-flash:
+    provider: openai_compatible
+    base_url:
+    api_key:
+    temperature: 3.0
+memory:
+  model: gpt-4o
+  reserve_ratio: 1.5
+"#).unwrap();
+        let issues = validate(&cfg);
+        let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(paths, vec![
+            "summary_model",
+            "memory.model",
+            "memory.reserve_ratio",
+            "models.gemini-2.5-flash.api_key",
+            "models.gemini-2.5-flash.base_url",
+            "models.gemini-2.5-flash.temperature",
+            "models.gpt-4o-mini.cost.max_output_tokens",
+        ]);
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        let cfg: Config = serde_yml::from_str("summary_model: a\nmodels:\n  a:\n    api_key: sk-xxx\n").unwrap();
+        assert!(validate(&cfg).is_empty());
+    }
+}
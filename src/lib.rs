@@ -3,7 +3,11 @@ pub mod config;
 pub mod agent;
 pub mod memory;
 pub mod prompt;
+pub mod telemetry;
 pub mod tool;
+pub mod trajectory;
+#[cfg(test)]
+mod test_support;
 
 pub fn test_logging() {
     tracing::info!("This is a test log from the library.");
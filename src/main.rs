@@ -3,7 +3,9 @@ use r_agent::tool::base::Tool;
 use serde_json::Value;
 use serde_json::json;
 use r_agent::config::config::*;
-use serde_json;
+use r_agent::config::layers::ConfigLoader;
+use r_agent::memory::export::{convert, MemoryLocation};
+use r_agent::telemetry::init_logging;
 
 #[derive(Debug)]
 struct CalculatorTool{
@@ -22,14 +24,105 @@ impl Tool for CalculatorTool {
     fn execute(&self, input: &str) -> String {
         // A very basic implementation that only handles addition for demonstration
         let args = serde_json::from_str::<serde_json::Value>(input).unwrap();
-        String::from(
-            format!("{}", args["num1"].as_f64().unwrap() + args["num2"].as_f64().unwrap())
-        )
+        format!("{}", args["num1"].as_f64().unwrap() + args["num2"].as_f64().unwrap())
+    }
+}
+
+/// `r_agent check-config [path] [--profile <name>] [--sources]` validates a config without running anything,
+/// `--sources` prints which layer every value came from
+fn check_config(args: &[String]) -> ! {
+    let usage = || -> ! {
+        eprintln!("Usage: r_agent check-config [path] [--profile <name>] [--sources]");
+        std::process::exit(2);
+    };
+    let mut path = None;
+    let mut profile = None;
+    let mut show_sources = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--profile" => profile = Some(args.next().unwrap_or_else(|| usage()).as_str()),
+            "--sources" => show_sources = true,
+            flag if flag.starts_with("--") => usage(),
+            // a second path is a typo rather than something to silently prefer
+            other if path.is_none() => path = Some(other),
+            _ => usage(),
+        }
+    }
+    let mut loader = ConfigLoader::new(path);
+    if let Some(profile) = profile {
+        loader = loader.profile(profile);
+    }
+    match loader.load() {
+        Ok(config) => {
+            if show_sources {
+                for (path, source) in config.sources.iter() {
+                    println!("{} <- {}", path, source);
+                }
+            }
+            println!("OK");
+            std::process::exit(0);
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `r_agent convert <from> <to> [--config <path>] [--model <name>] [--max-tokens <n>]` moves a conversation between
+/// memory backends, locations are `file:<path>` or `summary:<workspace>/<task_id>`.
+/// The model and token budget default to the `memory` section of the config.
+async fn convert_memory(args: &[String]) -> ! {
+    let usage = || -> ! {
+        eprintln!("Usage: r_agent convert <from> <to> [--config <path>] [--model <name>] [--max-tokens <n>]");
+        std::process::exit(2);
+    };
+    let mut locations = Vec::new();
+    let mut config_path = None;
+    let mut model = None;
+    let mut max_tokens = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(args.next().unwrap_or_else(|| usage()).as_str()),
+            "--model" => model = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--max-tokens" => max_tokens = Some(args.next().and_then(|n| n.parse::<usize>().ok()).unwrap_or_else(|| usage())),
+            other => locations.push(other),
+        }
+    }
+    let [from, to] = locations[..] else { usage() };
+    let result = async {
+        let from: MemoryLocation = from.parse()?;
+        let to: MemoryLocation = to.parse()?;
+        let config = ConfigLoader::new(config_path).load()?;
+        let mut memory = config.memory.clone();
+        memory.model = model.or(memory.model);
+        memory.max_tokens = max_tokens.or(memory.max_tokens);
+        convert(&config, memory.model_name(&config), memory.token_budget(&config), &from, &to).await
+    }.await;
+    match result {
+        Ok(export) => {
+            println!("Wrote {} messages with {} tokens", export.messages.len(), export.total_tokens());
+            std::process::exit(0);
+        },
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
     }
 }
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("check-config") {
+        check_config(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("convert") {
+        convert_memory(&args[2..]).await;
+    }
+
     let tool = CalculatorTool {
         config: json!({
             "name": "sumOfTwoNumbers",
@@ -53,6 +146,7 @@ async fn main() {
     };
 
     let config = load_config(None);
+    let _log_guard = init_logging(&config).expect("Failed to initialize logging");
     let model_name = "gpt-4o-mini";
     
     let memory = r_agent::memory::summary::SummaryMemory::new("tool_agent_example", 0.3, &config, model_name, "", 8192, "./workspace/");
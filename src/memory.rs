@@ -1,3 +1,9 @@
 pub mod base;
+pub mod composite;
+pub mod entity;
+pub mod export;
+pub mod registry;
+pub mod session;
+pub mod shared;
 pub mod sliding_window;
 pub mod summary;
\ No newline at end of file
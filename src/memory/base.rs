@@ -1,14 +1,55 @@
 use async_trait::async_trait;
+use tiktoken_rs::{tokenizer::{get_tokenizer, Tokenizer}, CoreBPE};
 use crate::model::schema::Message;
 
+/// The messages of a memory or agent history, boxed so the traits stay dyn-compatible
+pub type MessageIter<'a> = Box<dyn Iterator<Item = &'a Message> + Send + 'a>;
+
+/// The tokenizer of `model`, o200k for unknown models. Built once per encoding and shared by all memories.
+pub fn tokenizer(model: &str) -> &'static CoreBPE {
+    match get_tokenizer(model) {
+        Some(Tokenizer::O200kHarmony) => tiktoken_rs::o200k_harmony_singleton(),
+        Some(Tokenizer::Cl100kBase) => tiktoken_rs::cl100k_base_singleton(),
+        Some(Tokenizer::P50kBase) => tiktoken_rs::p50k_base_singleton(),
+        Some(Tokenizer::P50kEdit) => tiktoken_rs::p50k_edit_singleton(),
+        Some(Tokenizer::R50kBase | Tokenizer::Gpt2) => tiktoken_rs::r50k_base_singleton(),
+        Some(Tokenizer::O200kBase) | None => tiktoken_rs::o200k_base_singleton(),
+    }
+}
+
+/// Tokens of `text` for `model`
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    tokenizer(model).encode_with_special_tokens(text).len()
+}
+
 #[async_trait]
 pub trait BaseMemory {
     // Add a message to the memory
     async fn add(&mut self, message:Message);
     // Get all messages from the memory
-    fn get_messages(&self) -> impl Iterator<Item = &Message>;
+    fn get_messages(&self) -> MessageIter<'_>;
     // Clear the memory
     fn clear(&mut self);
     // Get the token count of the memory
     fn token_count(&self) -> usize;
-}
\ No newline at end of file
+}
+
+/// A boxed memory, e.g. one chosen from config by `MemoryRegistry`, is a memory
+#[async_trait]
+impl <M: BaseMemory + Send + ?Sized> BaseMemory for Box<M> {
+    async fn add(&mut self, message: Message) {
+        (**self).add(message).await;
+    }
+
+    fn get_messages(&self) -> MessageIter<'_> {
+        (**self).get_messages()
+    }
+
+    fn clear(&mut self) {
+        (**self).clear();
+    }
+
+    fn token_count(&self) -> usize {
+        (**self).token_count()
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use async_trait::async_trait;
+use crate::{config::config::Config, memory::{base::{count_tokens, BaseMemory, MessageIter}, export::{ConversationExport, PortableMemory}}, model::schema::{Message, Role}};
+
+// pinned messages do not know the model they are sent to
+const PINNED_TOKENIZER: &str = "gpt-4o";
+
+/// Fixed messages, e.g. instructions that must stay in every request. Added messages are ignored.
+pub struct PinnedMemory {
+    messages: Vec<Message>,
+    // o200k tokens of each message
+    token_counts: Vec<usize>,
+}
+
+impl PinnedMemory {
+    pub fn new(messages: Vec<Message>) -> Self {
+        let token_counts = messages.iter().map(|m| count_tokens(PINNED_TOKENIZER, &m.content)).collect();
+        PinnedMemory { messages, token_counts }
+    }
+}
+
+#[async_trait]
+impl BaseMemory for PinnedMemory {
+    async fn add(&mut self, _message: Message) {}
+
+    fn get_messages(&self) -> MessageIter<'_> {
+        Box::new(self.messages.iter())
+    }
+
+    /// Pinned messages survive a clear
+    fn clear(&mut self) {}
+
+    fn token_count(&self) -> usize {
+        self.token_counts.iter().sum()
+    }
+}
+
+struct Component {
+    name: String,
+    memory: Box<dyn BaseMemory + Send>,
+    // higher priorities take their share of the budget first
+    priority: i32,
+    // at most this many tokens, `None` for whatever the higher priorities leave
+    max_tokens: Option<usize>,
+}
+
+/// Composite memory module
+/// Every message is added to all components. `get_messages` returns the components in the order they were added,
+/// each cut to the latest messages that fit its allocation, so the total never exceeds `max_tokens`.
+/// The leading system messages of a component, e.g. the facts of an `EntityMemory`, are always kept and
+/// only exceed the budget if they alone do. A tool call is kept or dropped together with its results.
+///
+/// Every component sees the whole conversation, so stack at most one that keeps a window of it:
+/// combine a `SlidingWindowMemory` with `EntityMemory::with_facts_only`, not with a plain `EntityMemory`.
+pub struct CompositeMemory {
+    model_str: String,
+    max_tokens: usize,
+    components: Vec<Component>,
+    // token counts by message content, only the messages the components held at the latest `select`
+    token_cache: HashMap<String, usize>,
+    // (component, message, tokens) of the messages within budget, in output order
+    selection: Vec<(usize, usize, usize)>,
+    selected_tokens: usize,
+}
+
+impl CompositeMemory {
+    pub fn new(model: &str, max_tokens: usize) -> Self {
+        CompositeMemory { model_str: model.to_string(), max_tokens, components: Vec::new(), token_cache: HashMap::new(), selection: Vec::new(), selected_tokens: 0 }
+    }
+
+    /// The budget is the `cost.max_input_tokens` of `model_name`
+    pub fn for_model(config: &Config, model_name: &str) -> Self {
+        let model_config = config.models.get(model_name).unwrap_or_else(|| panic!("Model {} not found in config", model_name));
+        let max_input_tokens = model_config.cost.as_ref().map(|cost| cost.max_input_tokens)
+                                .unwrap_or_else(|| panic!("Model {} has no cost.max_input_tokens", model_name));
+        Self::new(model_name, max_input_tokens)
+    }
+
+    /// Add a component, `name` identifies it in `component_tokens`
+    pub fn with_component<M: BaseMemory + Send + 'static>(mut self, name: &str, memory: M, priority: i32, max_tokens: Option<usize>) -> Self {
+        self.components.push(Component { name: name.to_string(), memory: Box::new(memory), priority, max_tokens });
+        self.select();
+        self
+    }
+
+    /// Tokens each component contributes to `get_messages`
+    pub fn component_tokens(&self) -> Vec<(String, usize)> {
+        self.components.iter().enumerate().map(|(i, component)| {
+            let tokens = self.selection.iter().filter(|(c, _, _)| *c == i).map(|(_, _, tokens)| tokens).sum();
+            (component.name.clone(), tokens)
+        }).collect()
+    }
+
+    /// Hand out the budget by priority, every component keeps its latest messages that fit
+    fn select(&mut self) {
+        let mut order: Vec<usize> = (0..self.components.len()).collect();
+        // stable, equal priorities keep the order they were added in
+        order.sort_by_key(|&i| std::cmp::Reverse(self.components[i].priority));
+
+        // only messages that are new since the last call are encoded
+        let mut previous = std::mem::take(&mut self.token_cache);
+        let counts: Vec<Vec<usize>> = self.components.iter().map(|component| {
+            component.memory.get_messages().map(|message| {
+                if let Some(&tokens) = self.token_cache.get(&message.content) {
+                    return tokens;
+                }
+                let (content, tokens) = previous.remove_entry(&message.content)
+                                            .unwrap_or_else(|| (message.content.clone(), count_tokens(&self.model_str, &message.content)));
+                self.token_cache.insert(content, tokens);
+                tokens
+            }).collect()
+        }).collect();
+
+        let mut remaining = self.max_tokens;
+        let mut kept: Vec<Vec<(usize, usize)>> = vec![Vec::new(); self.components.len()];
+        for i in order {
+            let component = &self.components[i];
+            let messages: Vec<&Message> = component.memory.get_messages().collect();
+            let mut budget = component.max_tokens.map_or(remaining, |max| max.min(remaining));
+
+            // the leading system messages are what some components are for
+            let head = messages.iter().take_while(|m| m.role == Role::SYSTEM).count();
+            for (index, &tokens) in counts[i][..head].iter().enumerate() {
+                budget = budget.saturating_sub(tokens);
+                remaining = remaining.saturating_sub(tokens);
+                kept[i].push((index, tokens));
+            }
+
+            // the rest is cut from the newest message back, a tool call together with its results
+            let mut end = messages.len();
+            while end > head {
+                let start = (head..end).rev().find(|&m| messages[m].role != Role::TOOL);
+                let start = match start {
+                    Some(start) if messages[end - 1].role != Role::TOOL || messages[start].tool_calls.is_some() => start,
+                    // results whose call the component already dropped, the API would reject them
+                    Some(start) => {
+                        end = start + 1;
+                        continue;
+                    },
+                    None => break,
+                };
+                let tokens: usize = counts[i][start..end].iter().sum();
+                if tokens > budget {
+                    break;
+                }
+                budget -= tokens;
+                remaining -= tokens;
+                kept[i].extend((start..end).map(|m| (m, counts[i][m])));
+                end = start;
+            }
+            if kept[i].len() < messages.len() {
+                tracing::debug!("CompositeMemory: {} keeps {} of its messages", component.name, kept[i].len());
+            }
+        }
+        self.selection = kept.into_iter().enumerate()
+                            .flat_map(|(c, mut indices)| {
+                                indices.sort_unstable();
+                                indices.into_iter().map(move |(m, tokens)| (c, m, tokens))
+                            })
+                            .collect();
+        self.selected_tokens = self.selection.iter().map(|(_, _, tokens)| tokens).sum();
+    }
+}
+
+#[async_trait]
+impl BaseMemory for CompositeMemory {
+    async fn add(&mut self, message: Message) {
+        for component in self.components.iter_mut() {
+            component.memory.add(message.clone()).await;
+        }
+        self.select();
+    }
+
+    fn get_messages(&self) -> MessageIter<'_> {
+        let messages: Vec<Vec<&Message>> = self.components.iter().map(|c| c.memory.get_messages().collect()).collect();
+        let selected: Vec<&Message> = self.selection.iter().filter_map(|(c, m, _)| messages[*c].get(*m).copied()).collect();
+        Box::new(selected.into_iter())
+    }
+
+    fn token_count(&self) -> usize {
+        self.selected_tokens
+    }
+
+    fn clear(&mut self) {
+        for component in self.components.iter_mut() {
+            component.memory.clear();
+        }
+        self.select();
+    }
+}
+
+#[async_trait]
+impl PortableMemory for PinnedMemory {
+    fn export(&self) -> ConversationExport {
+        ConversationExport::new("pinned", None, self.messages.iter().cloned().zip(self.token_counts.iter().copied()))
+    }
+
+    /// Pins the imported messages instead of the current ones
+    async fn import(&mut self, export: ConversationExport) {
+        *self = PinnedMemory::new(export.messages_with_summary());
+    }
+}
+
+#[async_trait]
+impl PortableMemory for CompositeMemory {
+    /// Exports the messages within budget, as `get_messages` returns them
+    fn export(&self) -> ConversationExport {
+        ConversationExport::new("composite", None, self.get_messages().cloned().zip(self.selection.iter().map(|(_, _, tokens)| *tokens)))
+    }
+
+    /// Every component gets the imported messages as if they were added
+    async fn import(&mut self, export: ConversationExport) {
+        self.clear();
+        for message in export.messages_with_summary() {
+            self.add(message).await;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::{entity::EntityMemory, sliding_window::SlidingWindowMemory}, test_support::*};
+
+    #[tokio::test]
+    async fn test_composite_memory_budget() {
+        let pinned = PinnedMemory::new(vec![Message::system("Always answer in English.")]);
+        let mut memory = CompositeMemory::new("gpt-4o-mini", 24)
+                            .with_component("pinned", pinned, 10, None)
+                            .with_component("window", SlidingWindowMemory::new(100, "gpt-4o-mini", 100000), 0, None);
+        for i in 0..8 {
+            memory.add(Message::user(&format!("message number {}", i))).await;
+        }
+        let messages: Vec<&Message> = memory.get_messages().collect();
+        assert!(memory.token_count() <= 24);
+        assert!(messages.len() < 9);
+        assert_eq!(messages[0].content, "Always answer in English.");
+        assert_eq!(messages.last().unwrap().content, "message number 7");
+        // the window keeps a contiguous run of the latest messages
+        assert!(messages[1].content.starts_with("message number "));
+        let tokens: usize = memory.component_tokens().iter().map(|(_, tokens)| tokens).sum();
+        assert_eq!(tokens, memory.token_count());
+
+        memory.clear();
+        assert_eq!(memory.get_messages().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_composite_memory_allocations() {
+        let mut memory = CompositeMemory::new("gpt-4o-mini", 20)
+                            .with_component("window", SlidingWindowMemory::new(100, "gpt-4o-mini", 100000), 0, None)
+                            .with_component("notes", SlidingWindowMemory::new(100, "gpt-4o-mini", 100000), 1, Some(8));
+        for i in 0..5 {
+            memory.add(Message::user(&format!("message number {}", i))).await;
+        }
+        // notes are served first up to their allocation, the window gets what is left
+        let tokens = memory.component_tokens();
+        assert!(tokens[1].1 <= 8 && tokens[1].1 > 0);
+        assert!(tokens[0].1 + tokens[1].1 <= 20);
+        let messages: Vec<&Message> = memory.get_messages().collect();
+        assert_eq!(messages.last().unwrap().content, "message number 4");
+    }
+
+    #[tokio::test]
+    async fn test_pinned_entity_and_window() {
+        let workspace = "./workspace_test/composite_entity";
+        let _ = std::fs::remove_dir_all(workspace);
+        let config = offline_config();
+        let conversation = [
+            Message::user("I'm Ada. What is the weather in Paris?"),
+            Message::assistant("Let me look that up for you.", Some(vec![tool_call("call_0", "weather", r#"{"city": "Paris"}"#)])),
+            Message::tool("Sunny, 25 degrees, light wind from the west", None, Some("call_0".to_string())),
+            Message::assistant("It is sunny and 25 degrees in Paris.", None),
+            Message::user("What should Ada wear?"),
+        ];
+        let mut windows = Vec::new();
+        for budget in (10..=80).step_by(5) {
+            let extraction = FnModel(|_: &[&Message]| text(r#"{"entities": [{"name": "Ada", "kind": "person", "facts": ["travels to Paris"]}]}"#));
+            let entities = EntityMemory::new("task", &config, MODEL, 1000, workspace).with_extraction_batch(1).with_facts_only().map_model(|_| Box::new(extraction));
+            let mut memory = CompositeMemory::new(MODEL, budget)
+                                .with_component("pinned", PinnedMemory::new(vec![Message::system("Answer briefly.")]), 10, None)
+                                .with_component("entities", entities, 5, None)
+                                .with_component("window", SlidingWindowMemory::new(100, MODEL, 100000), 0, None);
+            for message in conversation.iter().cloned() {
+                memory.add(message).await;
+            }
+            let messages: Vec<&Message> = memory.get_messages().collect();
+
+            // the facts are kept however tight the budget, and the conversation is not repeated
+            assert_eq!(messages[0].content, "Answer briefly.");
+            assert!(messages[1].role == Role::SYSTEM && messages[1].content.contains("- Ada (person): travels to Paris"));
+            assert!(messages[2..].iter().all(|m| m.role != Role::SYSTEM));
+            let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+            assert!(contents.iter().all(|c| contents.iter().filter(|other| *other == c).count() == 1));
+            // a tool result only comes right after its call
+            for (i, message) in messages.iter().enumerate().filter(|(_, m)| m.role == Role::TOOL) {
+                assert!(messages[i - 1].tool_calls.is_some(), "budget {}: orphan tool result {:?}", budget, message.content);
+            }
+            let window = memory.component_tokens()[2].1;
+            assert!(window == 0 || memory.token_count() <= budget);
+            windows.push(messages.len() - 2);
+        }
+        // budgets that end the window right before the tool result skip the call with it
+        assert!(!windows.contains(&3));
+        assert_eq!(windows.last(), Some(&5));
+        std::fs::remove_dir_all(workspace).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::Context;
+use std::{collections::BTreeMap, fs, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use async_trait::async_trait;
+use crate::{memory::{base::{count_tokens, tokenizer, BaseMemory, MessageIter}, export::{ConversationExport, PortableMemory}},
+            config::config::{default_memory_extraction_batch, Config},
+            model::{base::BaseModel, cache::CachedModel, litellm_model::LitellmModel, options::GenerationOptions, schema::{Message, Role::*}, structured::StructuredOutput},
+            prompt::summary::*};
+
+/// An entity and everything the conversation established about it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Entity {
+    /// Name as used in the conversation, e.g. "Ada Lovelace" or "order 1234"
+    pub name: String,
+    /// person, organization, id, preference, configuration, ...
+    pub kind: String,
+    /// Short self-contained facts
+    pub facts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct EntityUpdates {
+    entities: Vec<Entity>,
+}
+
+/// Entity memory module
+/// Keeps a window of recent messages and a store of entities the model extracts from the user and assistant messages.
+/// Extraction runs once `extraction_batch` messages are pending (the `memory.extraction_batch` default unless set),
+/// one call for the whole batch, `flush` runs it early.
+/// The store is persisted as `entities.json` in the workspace, only entities relevant to the latest message are
+/// injected as a system message in front of the window. `with_facts_only` drops the window, e.g. in a `CompositeMemory`.
+pub struct EntityMemory {
+    model_str: String,
+    extraction_model: Box<dyn BaseModel>,
+    extraction_batch: usize,
+    // keep no window, only serve the facts
+    facts_only: bool,
+    max_tokens: usize,
+    workspace_path: PathBuf,
+    messages: Vec<Message>,
+    token_counts: Vec<usize>,
+    // not yet sent for extraction
+    pending: Vec<Message>,
+    // keyed by lowercase name
+    entities: BTreeMap<String, Entity>,
+    context: Message,
+}
+
+impl EntityMemory {
+    pub fn new(task_id: &str, config: &Config, model_name: &str, max_tokens: usize, workspace_path: &str) -> Self {
+        let model_config = config.models.get(model_name).unwrap_or_else(|| panic!("Model {} not found in config", model_name));
+        let extraction_model = CachedModel::wrap_if_enabled(Box::new(LitellmModel::new(model_name, model_config, "")), model_config);
+
+        let mut ret = EntityMemory {
+            model_str: model_name.to_string(),
+            extraction_model,
+            extraction_batch: default_memory_extraction_batch(),
+            facts_only: false,
+            max_tokens,
+            workspace_path: PathBuf::from(workspace_path).join(task_id),
+            messages: Vec::new(),
+            token_counts: Vec::new(),
+            pending: Vec::new(),
+            entities: BTreeMap::new(),
+            context: Message::system(""),
+        };
+        if !ret.workspace_path.exists() {
+            fs::create_dir_all(&ret.workspace_path).with_context(|| format!("Failed to create workspace directory: {:?}", &ret.workspace_path)).unwrap();
+        }
+        ret.load_entities();
+        ret
+    }
+
+    /// Wrap the extraction model, e.g. to record or replay it in tests
+    pub fn map_model<F: FnOnce(Box<dyn BaseModel>) -> Box<dyn BaseModel>>(mut self, f: F) -> Self {
+        self.extraction_model = f(self.extraction_model);
+        self
+    }
+
+    /// Extract from `batch` messages at a time instead of every message, 0 turns extraction off
+    pub fn with_extraction_batch(mut self, batch: usize) -> Self {
+        self.extraction_batch = batch;
+        self
+    }
+
+    /// Serve only the facts message, added messages are still extracted from but not kept.
+    /// For a `CompositeMemory` that already has a window of the conversation.
+    pub fn with_facts_only(mut self) -> Self {
+        self.facts_only = true;
+        self.clear();
+        self
+    }
+
+    /// Extract from the pending messages now, e.g. before reading the store or at the end of a run
+    pub async fn flush(&mut self) {
+        let updated = self.extract_pending().await;
+        let latest = self.messages.last().map(|m| m.content.clone()).unwrap_or_default();
+        self.update_context(&latest, updated);
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.values()
+    }
+
+    pub fn entity(&self, name: &str) -> Option<&Entity> {
+        self.entities.get(&Self::key(name))
+    }
+
+    /// Remove an entity from the store and the workspace
+    pub fn forget(&mut self, name: &str) -> Option<Entity> {
+        let removed = self.entities.remove(&Self::key(name));
+        if removed.is_some() {
+            self.save_entities();
+        }
+        removed
+    }
+
+    fn key(name: &str) -> String {
+        name.trim().to_lowercase()
+    }
+
+    /// Entities whose name appears in `text` as a whole word, "Ada" is not mentioned in "Canada"
+    fn mentioned_in(&self, text: &str) -> Vec<String> {
+        let text = text.to_lowercase();
+        self.entities.keys().filter(|key| Self::mentions(&text, key)).cloned().collect()
+    }
+
+    fn mentions(text: &str, key: &str) -> bool {
+        text.match_indices(key).any(|(start, _)| {
+            let before = text[..start].chars().next_back();
+            let after = text[start + key.len()..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+    }
+
+    fn format_entities<'a>(entities: impl Iterator<Item = &'a Entity>) -> String {
+        let lines: Vec<String> = entities.map(|e| format!("- {} ({}): {}", e.name, e.kind, e.facts.join("; "))).collect();
+        if lines.is_empty() { "(none)".to_string() } else { lines.join("\n") }
+    }
+
+    /// Ask the model which entities the pending messages add or change and merge them into the store.
+    /// Returns the keys of the updated entities.
+    async fn extract_pending(&mut self) -> Vec<String> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let messages = std::mem::take(&mut self.pending);
+        let mut mentioned: Vec<String> = messages.iter().flat_map(|m| self.mentioned_in(&m.content)).collect();
+        mentioned.sort();
+        mentioned.dedup();
+        let known = self.entities.values().map(|e| format!("- {} ({})", e.name, e.kind)).collect::<Vec<String>>();
+        let prompt = ENTITY_EXTRACTION_PROMPT.replace("{known}", &if known.is_empty() { "(none)".to_string() } else { known.join("\n") })
+                                             .replace("{facts}", &Self::format_entities(mentioned.iter().filter_map(|key| self.entities.get(key))))
+                                             .replace("{messages}", &messages.iter().map(|m| format!("{}: {}", m.role, m.content)).collect::<Vec<_>>().join("\n\n"));
+        let updates = match self.extraction_model.call_structured::<EntityUpdates>(vec![&Message::user(&prompt)], &GenerationOptions::default()).await {
+            Ok(updates) => updates.entities,
+            Err(e) => {
+                tracing::error!("Failed to extract entities: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut updated = Vec::new();
+        for entity in updates.into_iter().filter(|e| !e.name.trim().is_empty()) {
+            let key = Self::key(&entity.name);
+            tracing::debug!("EntityMemory: updated {} with {:?}", entity.name, entity.facts);
+            self.entities.insert(key.clone(), entity);
+            updated.push(key);
+        }
+        if !updated.is_empty() {
+            self.save_entities();
+        }
+        updated
+    }
+
+    fn update_context(&mut self, latest: &str, updated: Vec<String>) {
+        let mut relevant = self.mentioned_in(latest);
+        relevant.extend(updated);
+        relevant.sort();
+        relevant.dedup();
+        self.context.content = if relevant.is_empty() {
+            String::new()
+        } else {
+            ENTITY_CONTEXT_FORMAT.replace("{entities}", &Self::format_entities(relevant.iter().filter_map(|key| self.entities.get(key))))
+        };
+    }
+
+    fn entities_file(&self) -> PathBuf {
+        self.workspace_path.join("entities.json")
+    }
+
+    fn load_entities(&mut self) {
+        let path = self.entities_file();
+        if !path.exists() {
+            return;
+        }
+        match fs::read_to_string(&path).map_err(anyhow::Error::from).and_then(|content| Ok(serde_json::from_str::<Vec<Entity>>(&content)?)) {
+            Ok(entities) => self.entities = entities.into_iter().map(|e| (Self::key(&e.name), e)).collect(),
+            Err(e) => tracing::error!("Failed to load entities from {:?}: {}", path, e),
+        }
+    }
+
+    fn save_entities(&self) {
+        let path = self.entities_file();
+        let entities: Vec<&Entity> = self.entities.values().collect();
+        if let Err(e) = fs::write(&path, serde_json::to_string_pretty(&entities).unwrap()) {
+            tracing::error!("Failed to save entities to file {:?}: {}", path, e);
+        }
+    }
+
+    fn context_tokens(&self) -> usize {
+        if self.context.content.is_empty() { 0 } else { count_tokens(&self.model_str, &self.context.content) }
+    }
+
+    fn truncate(&mut self) {
+        let context = self.context_tokens();
+        while self.messages.len() > 1 && context + self.token_counts.iter().sum::<usize>() > self.max_tokens {
+            self.messages.remove(0);
+            self.token_counts.remove(0);
+        }
+    }
+}
+
+#[async_trait]
+impl BaseMemory for EntityMemory {
+    async fn add(&mut self, message: Message) {
+        // tool calls and results are too noisy to extract from, they still count as the latest message
+        if self.extraction_batch > 0 && matches!(message.role, USER | ASSISTANT) && message.tool_calls.is_none() && !message.content.trim().is_empty() {
+            self.pending.push(message.clone());
+        }
+        let updated = if self.extraction_batch > 0 && self.pending.len() >= self.extraction_batch {
+            self.extract_pending().await
+        } else {
+            Vec::new()
+        };
+        self.update_context(&message.content, updated);
+        if !self.facts_only {
+            self.token_counts.push(count_tokens(&self.model_str, &message.content));
+            self.messages.push(message);
+            self.truncate();
+        }
+    }
+
+    fn get_messages(&self) -> MessageIter<'_> {
+        let context = if self.context.content.is_empty() { None } else { Some(&self.context) };
+        Box::new(context.into_iter().chain(self.messages.iter()))
+    }
+
+    /// The window and the facts message
+    fn token_count(&self) -> usize {
+        self.context_tokens() + self.token_counts.iter().sum::<usize>()
+    }
+
+    /// Clears the messages, the entity store and messages pending extraction are kept
+    fn clear(&mut self) {
+        self.messages.clear();
+        self.token_counts.clear();
+        self.context.content.clear();
+    }
+}
+
+#[async_trait]
+impl PortableMemory for EntityMemory {
+    fn export(&self) -> ConversationExport {
+        ConversationExport::new("entity", None, self.messages.iter().cloned().zip(self.token_counts.iter().copied()))
+            .with_entities(self.entities.values().cloned().collect())
+    }
+
+    /// Imported entities replace stored ones with the same name, messages are not sent for extraction
+    async fn import(&mut self, export: ConversationExport) {
+        self.clear();
+        let messages = export.messages_with_summary();
+        let latest = messages.last().map(|m| m.content.clone()).unwrap_or_default();
+        if !self.facts_only {
+            let bpe = tokenizer(&self.model_str);
+            for message in messages {
+                self.token_counts.push(bpe.encode_with_special_tokens(message.content.as_str()).len());
+                self.messages.push(message);
+            }
+            self.truncate();
+        }
+        let updated: Vec<String> = export.entities.iter().map(|e| Self::key(&e.name)).collect();
+        self.entities.extend(export.entities.into_iter().map(|e| (Self::key(&e.name), e)));
+        self.save_entities();
+        self.update_context(&latest, updated);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    use crate::test_support::*;
+
+    #[tokio::test]
+    async fn test_entity_memory() {
+        let workspace = "./workspace_test/entity_memory";
+        let _ = fs::remove_dir_all(workspace);
+        let config = offline_config();
+        let model = ScriptedModel::new([
+            r#"{"entities": [{"name": "Ada", "kind": "person", "facts": ["account id 42", "prefers phone calls"]}]}"#,
+            r#"{"entities": []}"#,
+            r#"{"entities": [{"name": "Ada", "kind": "person", "facts": ["account id 42", "prefers email"]}]}"#,
+        ]);
+        let mut memory = EntityMemory::new("task", &config, "gpt-4o-mini", 1000, workspace).with_extraction_batch(1).map_model(|_| Box::new(model));
+
+        memory.add(Message::user("I'm Ada, account 42. Please call me.")).await;
+        memory.add(Message::user("What is the weather like?")).await;
+        // only entities relevant to the latest message are injected
+        assert_eq!(memory.get_messages().count(), 2);
+        memory.add(Message::user("Actually Ada prefers email now.")).await;
+        let messages: Vec<&Message> = memory.get_messages().collect();
+        assert_eq!(messages[0].role, SYSTEM);
+        assert!(messages[0].content.contains("- Ada (person): account id 42; prefers email"));
+        // tool results are not sent for extraction, the script has no reply left
+        memory.add(Message::tool("Ada: ticket created", None, Some("call_0".to_string()))).await;
+
+        let reloaded = EntityMemory::new("task", &config, "gpt-4o-mini", 1000, workspace);
+        assert_eq!(reloaded.entity("ada").unwrap().facts, vec!["account id 42", "prefers email"]);
+        fs::remove_dir_all(workspace).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extraction_batches() {
+        let workspace = "./workspace_test/entity_batches";
+        let _ = fs::remove_dir_all(workspace);
+        let config = offline_config();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let model = FnModel(move |history: &[&Message]| {
+            // one call per batch, with every message of it
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                assert!(history[0].content.contains("user: I'm Ada.\n\nassistant: Hello Ada!"));
+                text(r#"{"entities": [{"name": "Ada", "kind": "person", "facts": ["lives in Canada"]}]}"#)
+            } else {
+                text(r#"{"entities": []}"#)
+            }
+        });
+        let mut memory = EntityMemory::new("task", &config, MODEL, 1000, workspace).with_extraction_batch(2).map_model(|_| Box::new(model));
+        memory.add(Message::user("I'm Ada.")).await;
+        assert!(memory.entity("ada").is_none());
+        memory.add(Message::assistant("Hello Ada!", None)).await;
+        assert!(memory.entity("ada").is_some());
+
+        // names match whole words only
+        memory.add(Message::user("How cold is Canada in winter?")).await;
+        assert_eq!(memory.get_messages().count(), 3);
+        memory.add(Message::user("Is Ada's flight on time?")).await;
+        assert_eq!(memory.get_messages().next().unwrap().role, SYSTEM);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        fs::remove_dir_all(workspace).unwrap();
+    }
+}
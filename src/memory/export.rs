@@ -0,0 +1,186 @@
+use anyhow::Context;
+use std::{fs, path::{Path, PathBuf}, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use crate::{config::config::Config,
+            memory::{base::BaseMemory, entity::Entity, summary::{Summary, SummaryMemory}},
+            model::schema::Message};
+
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// A message and its token count in the memory that exported it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    pub tokens: usize,
+}
+
+/// Portable conversation format, moves a conversation between memory backends or to another machine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationExport {
+    pub version: u32,
+    /// The memory that wrote it, e.g. "summary"
+    pub source: String,
+    // unix seconds
+    pub exported_at: u64,
+    /// What the messages no longer contain, `None` if the source does not summarize
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<Entity>,
+    pub messages: Vec<ExportedMessage>,
+}
+
+impl ConversationExport {
+    pub fn new(source: &str, summary: Option<Summary>, messages: impl IntoIterator<Item = (Message, usize)>) -> Self {
+        ConversationExport {
+            version: EXPORT_FORMAT_VERSION,
+            source: source.to_string(),
+            exported_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            summary: summary.filter(|summary| !summary.is_empty()),
+            entities: Vec::new(),
+            messages: messages.into_iter().map(|(message, tokens)| ExportedMessage { message, tokens }).collect(),
+        }
+    }
+
+    pub fn with_entities(mut self, entities: Vec<Entity>) -> Self {
+        self.entities = entities;
+        self
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read export file: {:?}", path))?;
+        let export: ConversationExport = serde_json::from_str(&content).with_context(|| format!("Failed to parse export file: {:?}", path))?;
+        anyhow::ensure!(export.version == EXPORT_FORMAT_VERSION, "Unsupported export version {} in {:?}", export.version, path);
+        Ok(export)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create export directory: {:?}", parent))?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content).with_context(|| format!("Failed to write export file: {:?}", path))
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.messages.iter().map(|m| m.tokens).sum()
+    }
+
+    /// The messages for a backend without a summary, the summary goes first as a system message
+    pub fn messages_with_summary(&self) -> Vec<Message> {
+        let summary = self.summary.as_ref().map(|summary| Message::system(&summary.render()));
+        summary.into_iter().chain(self.messages.iter().map(|m| m.message.clone())).collect()
+    }
+}
+
+/// A memory that can be exported and restored from a `ConversationExport`
+#[async_trait]
+pub trait PortableMemory: BaseMemory {
+    fn export(&self) -> ConversationExport;
+    /// Replace the conversation with an export, the memory applies its own limits to it
+    async fn import(&mut self, export: ConversationExport);
+}
+
+/// Where a conversation is read from or written to by `convert`.
+/// `EntityMemory` only persists its entities, so it is not a location, its export goes through a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryLocation {
+    /// `file:<path>`, a `ConversationExport` as JSON
+    File(PathBuf),
+    /// `summary:<workspace>/<task_id>`, the workspace of a `SummaryMemory`
+    Summary { workspace: String, task_id: String },
+}
+
+impl FromStr for MemoryLocation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = s.split_once(':').with_context(|| format!("Expected <file|summary>:<path>, got {}", s))?;
+        let task = || -> anyhow::Result<(String, String)> {
+            let path = Path::new(path);
+            let task_id = path.file_name().and_then(|name| name.to_str()).with_context(|| format!("No task id in {:?}", path))?;
+            let workspace = path.parent().map(|p| p.to_string_lossy().to_string()).filter(|p| !p.is_empty()).unwrap_or(".".to_string());
+            Ok((workspace, task_id.to_string()))
+        };
+        match kind {
+            "file" => Ok(MemoryLocation::File(PathBuf::from(path))),
+            "summary" => task().map(|(workspace, task_id)| MemoryLocation::Summary { workspace, task_id }),
+            "entity" => anyhow::bail!("EntityMemory does not persist messages, export it to a file: location instead"),
+            other => anyhow::bail!("Unknown memory backend {}, expected file or summary", other),
+        }
+    }
+}
+
+/// Read a conversation from one location and write it to another, returns what the target holds afterwards.
+/// `model_name` and `max_tokens` configure the memories, importing more than `max_tokens` into a
+/// `SummaryMemory` summarizes with that model.
+pub async fn convert(config: &Config, model_name: &str, max_tokens: usize, from: &MemoryLocation, to: &MemoryLocation) -> anyhow::Result<ConversationExport> {
+    let export = match from {
+        MemoryLocation::File(path) => ConversationExport::load(path)?,
+        MemoryLocation::Summary { workspace, task_id } => SummaryMemory::new(task_id, 0.2, config, model_name, "", max_tokens, workspace).with_transcript().export(),
+    };
+    tracing::info!("Read {} messages with {} tokens from {:?}", export.messages.len(), export.total_tokens(), from);
+    match to {
+        MemoryLocation::File(path) => {
+            export.save(path)?;
+            Ok(export)
+        },
+        MemoryLocation::Summary { workspace, task_id } => {
+            let mut memory = SummaryMemory::new(task_id, 0.2, config, model_name, "", max_tokens, workspace).with_transcript();
+            memory.import(export).await;
+            Ok(memory.export())
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::sliding_window::SlidingWindowMemory, test_support::*};
+
+    #[tokio::test]
+    async fn test_convert_between_backends() {
+        let workspace = "./workspace_test/export";
+        let _ = fs::remove_dir_all(workspace);
+        let config = offline_config();
+        let call = tool_call("call_0", "lookup", r#"{"order": 42}"#);
+        let mut window = SlidingWindowMemory::new(10, "gpt-4o-mini", 1000);
+        window.add(Message::user("Where is order 42?")).await;
+        window.add(Message::assistant("", Some(vec![call]))).await;
+        window.add(Message::tool("shipped", None, Some("call_0".to_string()))).await;
+        window.add(Message::assistant("It has shipped.", None)).await;
+        let export = window.export();
+        assert_eq!(export.total_tokens(), window.token_count());
+        let file = PathBuf::from(workspace).join("prototype.json");
+        export.save(&file).unwrap();
+
+        let from: MemoryLocation = format!("file:{}", file.display()).parse().unwrap();
+        let to: MemoryLocation = format!("summary:{}/support", workspace).parse().unwrap();
+        assert_eq!(to, MemoryLocation::Summary { workspace: workspace.to_string(), task_id: "support".to_string() });
+        let stored = convert(&config, "gpt-4o-mini", 1000, &from, &to).await.unwrap();
+        assert_eq!(stored.source, "summary");
+        assert_eq!(stored.messages.len(), 4);
+
+        // the summary store persisted it, reading it back keeps tool calls and results
+        let copy: MemoryLocation = format!("file:{}/copy.json", workspace).parse().unwrap();
+        let copied = convert(&config, "gpt-4o-mini", 1000, &to, &copy).await.unwrap();
+        assert_eq!(copied.messages[1].message.tool_calls.as_ref().unwrap()[0].function.name, "lookup");
+        assert_eq!(copied.messages[2].message.tool_call_id.as_deref(), Some("call_0"));
+
+        // a summary becomes the first message of a backend without one
+        let mut summarized = ConversationExport::load(&PathBuf::from(workspace).join("copy.json")).unwrap();
+        summarized.summary = Some(Summary { task_context: "Order support".to_string(), ..Summary::default() });
+        let mut restored = SlidingWindowMemory::new(10, "gpt-4o-mini", 1000);
+        restored.import(summarized).await;
+        let messages: Vec<&Message> = restored.get_messages().collect();
+        assert_eq!(messages.len(), 5);
+        assert!(messages[0].content.contains("Order support"));
+
+        assert!("window:x".parse::<MemoryLocation>().is_err());
+        assert!(format!("entity:{}/support", workspace).parse::<MemoryLocation>().is_err());
+        fs::remove_dir_all(workspace).unwrap();
+    }
+}
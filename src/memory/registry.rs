@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+use crate::{config::config::{Config, MemoryConfig},
+            memory::{base::BaseMemory, entity::EntityMemory, sliding_window::SlidingWindowMemory, summary::SummaryMemory}};
+
+/// Builds a memory for a task from its config section
+pub type MemoryFactory = Box<dyn Fn(&Config, &MemoryConfig, &str) -> Box<dyn BaseMemory + Send> + Send + Sync>;
+
+/// Memory registry
+/// Maps the `memory.kind` of the config to a factory, so the memory of an agent is chosen at runtime.
+/// `Default` registers sliding_window, summary and entity, `with_factory` adds or replaces kinds.
+pub struct MemoryRegistry {
+    factories: BTreeMap<String, MemoryFactory>,
+}
+
+impl Default for MemoryRegistry {
+    fn default() -> Self {
+        MemoryRegistry::new()
+            .with_factory("sliding_window", |config, memory, _| {
+                Box::new(SlidingWindowMemory::new(memory.max_messages, memory.model_name(config), memory.token_budget(config)))
+            })
+            .with_factory("summary", |config, memory, task_id| {
+                Box::new(SummaryMemory::new(task_id, memory.reserve_ratio, config, memory.model_name(config), "", memory.token_budget(config), &memory.workspace))
+            })
+            .with_factory("entity", |config, memory, task_id| {
+                Box::new(EntityMemory::new(task_id, config, memory.model_name(config), memory.token_budget(config), &memory.workspace)
+                            .with_extraction_batch(memory.extraction_batch))
+            })
+    }
+}
+
+impl MemoryRegistry {
+    /// A registry without any kinds
+    pub fn new() -> Self {
+        MemoryRegistry { factories: BTreeMap::new() }
+    }
+
+    pub fn with_factory<F>(mut self, kind: &str, factory: F) -> Self
+    where F: Fn(&Config, &MemoryConfig, &str) -> Box<dyn BaseMemory + Send> + Send + Sync + 'static {
+        self.factories.insert(kind.to_string(), Box::new(factory));
+        self
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    pub fn build(&self, config: &Config, memory: &MemoryConfig, task_id: &str) -> anyhow::Result<Box<dyn BaseMemory + Send>> {
+        let factory = self.factories.get(&memory.kind).ok_or_else(|| {
+            anyhow::anyhow!("Unknown memory kind {}, expected one of {}", memory.kind, self.kinds().collect::<Vec<_>>().join(", "))
+        })?;
+        Ok(factory(config, memory, task_id))
+    }
+
+    /// The memory of the `memory` section of `config`
+    pub fn from_config(&self, config: &Config, task_id: &str) -> anyhow::Result<Box<dyn BaseMemory + Send>> {
+        self.build(config, &config.memory, task_id)
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::Context;
+use std::{fs, path::{Path, PathBuf}, time::UNIX_EPOCH};
+use crate::{memory::summary::{read_transcript, SummaryHierarchy, SUMMARY_FILE, TRANSCRIPT_FILE}, model::schema::Message};
+
+/// Archived sessions are moved into this directory of the workspace
+pub const ARCHIVE_DIR: &str = ".archive";
+
+/// A session in the listing of a `SessionManager`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    /// The `task_id` of the memory that wrote it
+    pub id: String,
+    pub archived: bool,
+    /// Messages in the transcript
+    pub messages: usize,
+    /// Messages not yet summarized or cleared
+    pub window: usize,
+    // unix seconds of the latest write
+    pub modified: u64,
+}
+
+/// A session loaded from disk
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub id: String,
+    pub archived: bool,
+    pub summary: SummaryHierarchy,
+    pub transcript: Vec<Message>,
+}
+
+impl Session {
+    /// The messages a `SummaryMemory` resuming this session starts with
+    pub fn window(&self) -> &[Message] {
+        &self.transcript[self.summary.transcript_offset.min(self.transcript.len())..]
+    }
+}
+
+/// Session manager
+/// Manages the per-task directories `SummaryMemory` writes into a workspace. A session is resumed by creating
+/// a `SummaryMemory` with its id and `with_transcript`, forks start from a copy of the summaries and the transcript.
+pub struct SessionManager {
+    workspace_path: PathBuf,
+}
+
+impl SessionManager {
+    pub fn new(workspace_path: &str) -> Self {
+        SessionManager { workspace_path: PathBuf::from(workspace_path) }
+    }
+
+    /// Active sessions followed by archived ones, each sorted by id
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions = Self::list_dir(&self.workspace_path, false);
+        sessions.extend(Self::list_dir(&self.archive_path(), true));
+        sessions
+    }
+
+    pub fn load(&self, id: &str) -> anyhow::Result<Session> {
+        let (path, archived) = self.find(id)?;
+        let summary_path = path.join(SUMMARY_FILE);
+        let summary = if summary_path.exists() {
+            SummaryHierarchy::load(&summary_path)?
+        } else {
+            SummaryHierarchy::default()
+        };
+        let transcript = read_transcript(&path.join(TRANSCRIPT_FILE))?;
+        Ok(Session { id: id.to_string(), archived, summary, transcript })
+    }
+
+    /// Copy a session to `new_id`, the copy is active even if the source is archived
+    pub fn fork(&self, id: &str, new_id: &str) -> anyhow::Result<()> {
+        let (source, _) = self.find(id)?;
+        Self::check_id(new_id)?;
+        anyhow::ensure!(self.find(new_id).is_err(), "Session {} already exists", new_id);
+        let target = self.workspace_path.join(new_id);
+        fs::create_dir_all(&target).with_context(|| format!("Failed to create session directory: {:?}", target))?;
+        for entry in fs::read_dir(&source).with_context(|| format!("Failed to read session directory: {:?}", source))? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::copy(entry.path(), target.join(entry.file_name()))
+                    .with_context(|| format!("Failed to copy {:?} into {:?}", entry.path(), target))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move a session out of the listing of active sessions, `restore` brings it back
+    pub fn archive(&self, id: &str) -> anyhow::Result<()> {
+        let (path, archived) = self.find(id)?;
+        anyhow::ensure!(!archived, "Session {} is already archived", id);
+        fs::create_dir_all(self.archive_path()).with_context(|| format!("Failed to create archive directory: {:?}", self.archive_path()))?;
+        fs::rename(&path, self.archive_path().join(id)).with_context(|| format!("Failed to archive session {}", id))
+    }
+
+    pub fn restore(&self, id: &str) -> anyhow::Result<()> {
+        let (path, archived) = self.find(id)?;
+        anyhow::ensure!(archived, "Session {} is not archived", id);
+        fs::rename(&path, self.workspace_path.join(id)).with_context(|| format!("Failed to restore session {}", id))
+    }
+
+    /// Remove an active or archived session from disk
+    pub fn delete(&self, id: &str) -> anyhow::Result<()> {
+        let (path, _) = self.find(id)?;
+        fs::remove_dir_all(&path).with_context(|| format!("Failed to delete session directory: {:?}", path))
+    }
+
+    fn archive_path(&self) -> PathBuf {
+        self.workspace_path.join(ARCHIVE_DIR)
+    }
+
+    fn check_id(id: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(!id.is_empty() && !id.starts_with('.') && !id.contains(['/', '\\']), "Invalid session id {:?}", id);
+        Ok(())
+    }
+
+    /// The directory of a session and whether it is archived
+    fn find(&self, id: &str) -> anyhow::Result<(PathBuf, bool)> {
+        Self::check_id(id)?;
+        let active = self.workspace_path.join(id);
+        if active.is_dir() {
+            return Ok((active, false));
+        }
+        let archived = self.archive_path().join(id);
+        if archived.is_dir() {
+            return Ok((archived, true));
+        }
+        anyhow::bail!("Session {} not found in {:?}", id, self.workspace_path)
+    }
+
+    fn list_dir(dir: &Path, archived: bool) -> Vec<SessionInfo> {
+        let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+        let mut sessions: Vec<SessionInfo> = entries.filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok().filter(|id| !id.starts_with('.')).map(|id| (id, entry.path())))
+            .map(|(id, path)| {
+                let messages = match read_transcript(&path.join(TRANSCRIPT_FILE)) {
+                    Ok(transcript) => transcript.len(),
+                    Err(e) => {
+                        tracing::error!("Failed to read transcript of session {}: {:#}", id, e);
+                        0
+                    }
+                };
+                let offset = SummaryHierarchy::load(&path.join(SUMMARY_FILE)).map_or(0, |summary| summary.transcript_offset);
+                let modified = fs::read_dir(&path).into_iter().flatten().filter_map(|entry| entry.ok())
+                                .filter_map(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+                                .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+                                .map(|d| d.as_secs())
+                                .max().unwrap_or(0);
+                SessionInfo { id, archived, messages, window: messages.saturating_sub(offset), modified }
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+        sessions
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::{base::BaseMemory, summary::{ClearPolicy, SummaryMemory}}, model::schema::Role, test_support::*};
+
+    const SUMMARY: &str = r#"{"task_context": "Chat", "key_decisions": [], "actions_taken": ["first chunk"], "current_state": "", "important_info": []}"#;
+
+    fn message(i: usize) -> Message {
+        Message::user(&format!("message {} {}", i, "word ".repeat(60)))
+    }
+
+    #[tokio::test]
+    async fn test_session_manager() {
+        let workspace = "./workspace_test/sessions";
+        let _ = fs::remove_dir_all(workspace);
+        let config = offline_config();
+        let mut memory = SummaryMemory::new("chat", 0.2, &config, MODEL, "", 200, workspace)
+                            .with_transcript()
+                            .map_model(|_| Box::new(ScriptedModel::new([SUMMARY])));
+        for i in 0..4 {
+            memory.add(message(i)).await;
+        }
+        let window: Vec<String> = memory.get_messages().filter(|m| m.role == Role::USER).map(|m| m.content.clone()).collect();
+        drop(memory);
+
+        // a new memory for the task starts from the summary, with the transcript it also resumes the window
+        let fresh = SummaryMemory::new("chat", 0.2, &config, MODEL, "", 200, workspace);
+        assert_eq!(fresh.get_messages().filter(|m| m.role == Role::USER).count(), 0);
+        assert_eq!(fresh.summaries().rolling.actions_taken, vec!["first chunk"]);
+        drop(fresh);
+        let resumed = SummaryMemory::new("chat", 0.2, &config, MODEL, "", 200, workspace).with_transcript();
+        assert_eq!(resumed.get_messages().filter(|m| m.role == Role::USER).map(|m| m.content.clone()).collect::<Vec<_>>(), window);
+        assert_eq!(resumed.summaries().rolling.actions_taken, vec!["first chunk"]);
+
+        let manager = SessionManager::new(workspace);
+        let session = manager.load("chat").unwrap();
+        assert_eq!(session.transcript.len(), 4);
+        assert_eq!(session.window().len(), window.len());
+
+        manager.fork("chat", "chat-fork").unwrap();
+        assert!(manager.fork("chat", "chat-fork").is_err());
+        manager.archive("chat").unwrap();
+        let listed: Vec<(String, bool, usize)> = manager.list().into_iter().map(|s| (s.id, s.archived, s.messages)).collect();
+        assert_eq!(listed, vec![("chat-fork".to_string(), false, 4), ("chat".to_string(), true, 4)]);
+        assert!(manager.load("chat").unwrap().archived);
+
+        manager.restore("chat").unwrap();
+        manager.delete("chat-fork").unwrap();
+        assert!(manager.load("chat-fork").is_err());
+        assert!(manager.load("../chat").is_err());
+        assert_eq!(manager.list().len(), 1);
+        fs::remove_dir_all(workspace).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clear_policies() {
+        let workspace = "./workspace_test/sessions_clear";
+        let _ = fs::remove_dir_all(workspace);
+        let config = offline_config();
+        let manager = SessionManager::new(workspace);
+        for (id, policy) in [("messages", ClearPolicy::Messages), ("summary", ClearPolicy::Summary), ("disk", ClearPolicy::Disk)] {
+            let mut memory = SummaryMemory::new(id, 0.2, &config, MODEL, "", 200, workspace)
+                                .with_transcript()
+                                .map_model(|_| Box::new(ScriptedModel::new([SUMMARY])))
+                                .with_clear_policy(policy);
+            for i in 0..4 {
+                memory.add(message(i)).await;
+            }
+            memory.clear();
+            memory.add(message(4)).await;
+        }
+
+        // cleared messages stay in the transcript but are not resumed
+        let messages = manager.load("messages").unwrap();
+        assert_eq!((messages.transcript.len(), messages.window().len()), (5, 1));
+        assert_eq!(messages.summary.rolling.actions_taken, vec!["first chunk"]);
+        let summary = manager.load("summary").unwrap();
+        assert_eq!((summary.transcript.len(), summary.window().len()), (5, 1));
+        assert!(summary.summary.rolling.is_empty() && summary.summary.sessions.is_empty());
+        let disk = manager.load("disk").unwrap();
+        assert_eq!((disk.transcript.len(), disk.window().len()), (1, 1));
+        fs::remove_dir_all(workspace).unwrap();
+    }
+}
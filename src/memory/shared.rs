@@ -0,0 +1,211 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock};
+use async_trait::async_trait;
+use tokio::sync::{watch, Mutex};
+use crate::{memory::base::{BaseMemory, MessageIter}, model::schema::Message};
+
+/// The messages of a memory at one point in time
+#[derive(Debug, Clone, Default)]
+pub struct MemorySnapshot {
+    /// Increases with every change, the value sent to subscribers
+    pub version: u64,
+    pub messages: Vec<Arc<Message>>,
+    pub token_count: usize,
+}
+
+impl MemorySnapshot {
+    pub fn to_messages(&self) -> Vec<Message> {
+        self.messages.iter().map(|m| m.as_ref().clone()).collect()
+    }
+}
+
+struct Shared<M> {
+    memory: Mutex<M>,
+    snapshot: RwLock<Arc<MemorySnapshot>>,
+    version: watch::Sender<u64>,
+    // set by a `clear` that found a write in progress, applied when that write finishes
+    clear_requested: AtomicBool,
+}
+
+/// Shared memory handle
+/// Wraps any memory so it can be used from several tokio tasks, e.g. an agent, a UI and a background job.
+/// Clones share the memory, writes are serialized and publish a new `MemorySnapshot` that readers get
+/// without waiting for a write in progress. `subscribe` notifies about every new snapshot.
+///
+/// A handle is itself a `BaseMemory`, so `ReactAgent<SharedMemory<M>>` works while other handles read along.
+/// Its `get_messages` serves the snapshot taken at the handle's latest write or `refresh`.
+pub struct SharedMemory<M: BaseMemory> {
+    shared: Arc<Shared<M>>,
+    view: Arc<MemorySnapshot>,
+}
+
+impl <M: BaseMemory> Clone for SharedMemory<M> {
+    fn clone(&self) -> Self {
+        SharedMemory { shared: self.shared.clone(), view: self.view.clone() }
+    }
+}
+
+impl <M: BaseMemory + Send> SharedMemory<M> {
+    pub fn new(memory: M) -> Self {
+        let snapshot = Arc::new(Self::take_snapshot(&memory, 0));
+        let (version, _) = watch::channel(0);
+        SharedMemory {
+            shared: Arc::new(Shared { memory: Mutex::new(memory), snapshot: RwLock::new(snapshot.clone()), version, clear_requested: AtomicBool::new(false) }),
+            view: snapshot,
+        }
+    }
+
+    /// The latest published messages
+    pub fn snapshot(&self) -> Arc<MemorySnapshot> {
+        self.shared.snapshot.read().unwrap().clone()
+    }
+
+    /// Receives the version of every new snapshot
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.shared.version.subscribe()
+    }
+
+    /// Add a message through a shared reference
+    pub async fn push(&self, message: Message) {
+        let mut memory = self.shared.memory.lock().await;
+        memory.add(message).await;
+        self.finish_write(&mut memory);
+    }
+
+    /// Clear through a shared reference
+    pub async fn reset(&self) {
+        let mut memory = self.shared.memory.lock().await;
+        memory.clear();
+        self.shared.clear_requested.store(false, Ordering::SeqCst);
+        self.publish(&memory);
+    }
+
+    /// Run `f` on the wrapped memory, e.g. to call methods of the concrete type, and publish the result
+    pub async fn update<R, F: FnOnce(&mut M) -> R>(&self, f: F) -> R {
+        let mut memory = self.shared.memory.lock().await;
+        let result = f(&mut memory);
+        self.finish_write(&mut memory);
+        result
+    }
+
+    /// Serve the latest snapshot from `get_messages`
+    pub fn refresh(&mut self) {
+        self.view = self.snapshot();
+    }
+
+    fn take_snapshot(memory: &M, version: u64) -> MemorySnapshot {
+        MemorySnapshot { version, messages: memory.get_messages().cloned().map(Arc::new).collect(), token_count: memory.token_count() }
+    }
+
+    // applies a `clear` requested during the write, then publishes
+    fn finish_write(&self, memory: &mut M) {
+        if self.shared.clear_requested.swap(false, Ordering::SeqCst) {
+            memory.clear();
+        }
+        self.publish(memory);
+    }
+
+    fn publish(&self, memory: &M) {
+        let mut current = self.shared.snapshot.write().unwrap();
+        let snapshot = Arc::new(Self::take_snapshot(memory, current.version + 1));
+        *current = snapshot.clone();
+        drop(current);
+        self.shared.version.send_replace(snapshot.version);
+    }
+}
+
+#[async_trait]
+impl <M: BaseMemory + Send> BaseMemory for SharedMemory<M> {
+    async fn add(&mut self, message: Message) {
+        self.push(message).await;
+        self.refresh();
+    }
+
+    fn get_messages(&self) -> MessageIter<'_> {
+        Box::new(self.view.messages.iter().map(|m| m.as_ref()))
+    }
+
+    /// Clears at once unless another handle is writing, then right after that write.
+    /// A deferred clear shows in `get_messages` after the next `refresh`.
+    fn clear(&mut self) {
+        let shared = self.shared.clone();
+        match shared.memory.try_lock() {
+            Ok(mut memory) => {
+                memory.clear();
+                shared.clear_requested.store(false, Ordering::SeqCst);
+                self.publish(&memory);
+                self.refresh();
+            },
+            Err(_) => shared.clear_requested.store(true, Ordering::SeqCst),
+        }
+    }
+
+    fn token_count(&self) -> usize {
+        self.view.token_count
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::base::BaseAgent, memory::sliding_window::SlidingWindowMemory, model::schema::Role, test_support::*};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_agent_behind_shared_memory() {
+        let config = offline_config();
+        let memory = SharedMemory::new(SlidingWindowMemory::new(50, MODEL, 100000));
+        let ui = memory.clone();
+        let mut updates = ui.subscribe();
+        let mut agent = react_agent(&config, "You are a helpful assistant.", memory, echo_model());
+
+        let run = tokio::spawn(async move {
+            let answer = agent.run("hello").await;
+            (answer, agent)
+        });
+        updates.changed().await.unwrap();
+        assert!(!ui.snapshot().messages.is_empty());
+        let (answer, mut agent) = run.await.unwrap();
+        assert_eq!(answer, "echo hello");
+
+        // the other handle sees what the agent wrote, then writes on its own
+        let snapshot = ui.snapshot();
+        let history: Vec<Message> = agent.get_history().cloned().collect();
+        assert_eq!(snapshot.to_messages().iter().map(|m| &m.content).collect::<Vec<_>>(), history.iter().map(|m| &m.content).collect::<Vec<_>>());
+        ui.push(Message::user("from the ui")).await;
+        assert_eq!(*updates.borrow_and_update(), ui.snapshot().version);
+        assert_eq!(agent.run("again").await, "echo again");
+        assert!(agent.get_history().any(|m| m.role == Role::USER && m.content == "from the ui"));
+
+        agent.clear_history();
+        assert!(ui.snapshot().messages.is_empty());
+        assert_eq!(ui.update(|memory| memory.token_count()).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_clear_during_write() {
+        let memory = SharedMemory::new(SlidingWindowMemory::new(50, MODEL, 100000));
+        memory.push(Message::user("first")).await;
+        let mut handle = memory.clone();
+        handle.refresh();
+
+        // another handle holds the lock, the clear waits for its write and the view keeps the messages
+        let guard = memory.shared.memory.lock().await;
+        handle.clear();
+        assert_eq!(handle.get_messages().count(), 1);
+        drop(guard);
+        // the write that finishes next applies it
+        memory.update(|_| ()).await;
+        assert!(memory.snapshot().messages.is_empty());
+        handle.refresh();
+        assert_eq!(handle.get_messages().count(), 0);
+
+        // a reset also takes care of a pending clear
+        memory.push(Message::user("second")).await;
+        let guard = memory.shared.memory.lock().await;
+        handle.clear();
+        drop(guard);
+        memory.reset().await;
+        memory.push(Message::user("third")).await;
+        assert_eq!(memory.snapshot().messages.len(), 1);
+    }
+}
@@ -1,6 +1,5 @@
-use crate::memory::base::BaseMemory;
+use crate::memory::{base::{count_tokens, BaseMemory, MessageIter}, export::{ConversationExport, PortableMemory}};
 use crate::model::schema::Message;
-use tiktoken_rs::{get_bpe_from_model, o200k_base};
 use async_trait::async_trait;
 
 pub struct SlidingWindowMemory {
@@ -38,14 +37,13 @@ impl SlidingWindowMemory {
 #[async_trait]
 impl BaseMemory for SlidingWindowMemory {
     async fn add(&mut self, message: Message) {
-        let bpe = get_bpe_from_model(&self.model_str).unwrap_or(o200k_base().unwrap());
-        self.token_counts.push(bpe.encode_with_special_tokens(message.content.as_str()).len());
+        self.token_counts.push(count_tokens(&self.model_str, &message.content));
         self.messages.push(message);
         self._truncate();
     }
 
-    fn get_messages(&self) -> impl Iterator<Item = &Message> {
-        self.messages.iter()
+    fn get_messages(&self) -> MessageIter<'_> {
+        Box::new(self.messages.iter())
     }
 
     fn token_count(&self) -> usize {
@@ -58,6 +56,20 @@ impl BaseMemory for SlidingWindowMemory {
     }
 }
 
+#[async_trait]
+impl PortableMemory for SlidingWindowMemory {
+    fn export(&self) -> ConversationExport {
+        ConversationExport::new("sliding_window", None, self.messages.iter().cloned().zip(self.token_counts.iter().copied()))
+    }
+
+    async fn import(&mut self, export: ConversationExport) {
+        self.clear();
+        for message in export.messages_with_summary() {
+            self.add(message).await;
+        }
+    }
+}
+
 
 // ------------------ Unit Test Module ------------------
 #[cfg(test)]
@@ -1,3 +1,8 @@
 pub mod base;
+pub mod cache;
 pub mod litellm_model;
-pub mod schema;
\ No newline at end of file
+pub mod metered;
+pub mod options;
+pub mod recording;
+pub mod schema;
+pub mod structured;
\ No newline at end of file
@@ -1,11 +1,67 @@
 use async_trait::async_trait;
-use crate::model::schema::{LLMResponse, Message};
+use serde_json::Value;
+use std::sync::Arc;
+use crate::model::{options::GenerationOptions, schema::{LLMResponse, Message}};
 
 #[async_trait]
-pub trait BaseModel {
+pub trait BaseModel: Send + Sync {
     async fn call(&self, user_prompt: &Message) -> LLMResponse;
     async fn call_with_history(
         &self,
         history: Vec<&Message>
     ) -> LLMResponse;
-}
\ No newline at end of file
+    /// Same as `call_with_history`, `options` are layered on top of `default_options()` for this call only.
+    /// Models without per-call settings ignore them.
+    async fn call_with_options(
+        &self,
+        history: Vec<&Message>,
+        options: &GenerationOptions,
+    ) -> LLMResponse {
+        let _ = options;
+        self.call_with_history(history).await
+    }
+    fn model_name(&self) -> &str;
+    // the system prompt and tools are baked into the model and sent with every request
+    fn system_prompt(&self) -> &str { "" }
+    fn tool_schemas(&self) -> &[Value] { &[] }
+    // generation settings from the model config
+    fn default_options(&self) -> GenerationOptions { GenerationOptions::default() }
+    // whether `ResponseFormat::JsonSchema` is enforced by the provider
+    fn supports_json_schema(&self) -> bool { false }
+}
+
+// a model shared between an owner and background tasks
+#[async_trait]
+impl <T: BaseModel + ?Sized> BaseModel for Arc<T> {
+    async fn call(&self, user_prompt: &Message) -> LLMResponse {
+        (**self).call(user_prompt).await
+    }
+
+    async fn call_with_history(&self, history: Vec<&Message>) -> LLMResponse {
+        (**self).call_with_history(history).await
+    }
+
+    async fn call_with_options(&self, history: Vec<&Message>, options: &GenerationOptions) -> LLMResponse {
+        (**self).call_with_options(history, options).await
+    }
+
+    fn model_name(&self) -> &str {
+        (**self).model_name()
+    }
+
+    fn system_prompt(&self) -> &str {
+        (**self).system_prompt()
+    }
+
+    fn tool_schemas(&self) -> &[Value] {
+        (**self).tool_schemas()
+    }
+
+    fn default_options(&self) -> GenerationOptions {
+        (**self).default_options()
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        (**self).supports_json_schema()
+    }
+}
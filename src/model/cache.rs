@@ -0,0 +1,302 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::{HashMap, VecDeque}, fs, path::PathBuf, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+use async_trait::async_trait;
+use crate::{config::config::{CacheConfig, ModelConfig},
+            model::{base::BaseModel, options::GenerationOptions, schema::{LLMResponse, Message, ModelRequest}}};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    created_at: u64,
+    response: LLMResponse,
+}
+
+/// Least recently used entries are at the front of `order`
+#[derive(Default)]
+struct LruCache {
+    entries: HashMap<String, CacheEntry>,
+    order: VecDeque<String>,
+}
+
+impl LruCache {
+    fn get(&mut self, key: &str) -> Option<CacheEntry> {
+        let entry = self.entries.get(key)?.clone();
+        self.touch(key);
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: String, entry: CacheEntry, capacity: usize) {
+        if self.entries.insert(key.clone(), entry).is_some() {
+            self.touch(&key);
+        } else {
+            self.order.push_back(key);
+        }
+        while self.entries.len() > capacity {
+            match self.order.pop_front() {
+                Some(oldest) => { self.entries.remove(&oldest); },
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+        self.order.retain(|k| k != key);
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(pos).unwrap();
+            self.order.push_back(key);
+        }
+    }
+}
+
+/// Cached model
+/// Serves identical requests (same model, system prompt, tools, sampling settings and messages)
+/// from an in-memory LRU and optionally an on-disk cache, cache hits are reported with zero cost.
+pub struct CachedModel {
+    inner: Box<dyn BaseModel>,
+    ttl_secs: Option<u64>,
+    capacity: usize,
+    dir: Option<PathBuf>,
+    lru: Mutex<LruCache>,
+}
+
+impl CachedModel {
+    pub fn new(inner: Box<dyn BaseModel>, settings: &CacheConfig) -> Self {
+        let dir = settings.dir.as_ref().map(PathBuf::from);
+        if let Some(dir) = dir.as_ref() && let Err(e) = fs::create_dir_all(dir) {
+            tracing::error!("Failed to create cache directory {:?}: {}", dir, e);
+        }
+        CachedModel {
+            inner,
+            ttl_secs: settings.ttl_secs,
+            capacity: settings.capacity.max(1),
+            dir,
+            lru: Mutex::new(LruCache::default()),
+        }
+    }
+
+    /// Wrap `inner` when caching is enabled for this model in the config
+    pub fn wrap_if_enabled(inner: Box<dyn BaseModel>, settings: &ModelConfig) -> Box<dyn BaseModel> {
+        match settings.cache.as_ref() {
+            Some(cache) if cache.enabled => Box::new(CachedModel::new(inner, cache)),
+            _ => inner,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn is_expired(&self, entry: &CacheEntry) -> bool {
+        match self.ttl_secs {
+            Some(ttl) => now_secs() >= entry.created_at + ttl,
+            None => false,
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Option<LLMResponse> {
+        let cached = self.lru.lock().unwrap().get(key);
+        let entry = match cached {
+            Some(entry) if self.is_expired(&entry) => {
+                self.lru.lock().unwrap().remove(key);
+                return None;
+            },
+            Some(entry) => entry,
+            // read without holding the lock, other calls keep hitting the in-memory entries meanwhile
+            None => {
+                let entry = self.load_from_disk(key).filter(|entry| !self.is_expired(entry))?;
+                self.lru.lock().unwrap().insert(key.to_string(), entry.clone(), self.capacity);
+                entry
+            }
+        };
+        Some(entry.response)
+    }
+
+    fn store(&self, key: String, response: &LLMResponse) {
+        let entry = CacheEntry { created_at: now_secs(), response: response.clone() };
+        if let Some(dir) = self.dir.as_ref() {
+            let path = dir.join(format!("{}.json", key));
+            match serde_json::to_string(&entry) {
+                Ok(content) => if let Err(e) = fs::write(&path, content) {
+                    tracing::error!("Failed to write cache entry {:?}: {}", path, e);
+                },
+                Err(e) => tracing::error!("Failed to serialize cache entry: {}", e),
+            }
+        }
+        self.lru.lock().unwrap().insert(key, entry, self.capacity);
+    }
+
+    fn load_from_disk(&self, key: &str) -> Option<CacheEntry> {
+        let path = self.dir.as_ref()?.join(format!("{}.json", key));
+        let content = fs::read_to_string(&path).ok()?;
+        serde_json::from_str(&content).map_err(|e| {
+            tracing::error!("Failed to parse cache entry {:?}: {}", path, e);
+            e
+        }).ok()
+    }
+}
+
+#[async_trait]
+impl BaseModel for CachedModel {
+    async fn call(&self, user_prompt: &Message) -> LLMResponse {
+        self.call_with_history(vec![user_prompt]).await
+    }
+
+    async fn call_with_history(
+            &self,
+            history: Vec<&Message>,
+        ) -> LLMResponse {
+        self.call_with_options(history, &GenerationOptions::default()).await
+    }
+
+    async fn call_with_options(
+            &self,
+            history: Vec<&Message>,
+            options: &GenerationOptions,
+        ) -> LLMResponse {
+        let key = ModelRequest::new(self, &history, options).key();
+        if let Some(mut response) = self.lookup(&key) {
+            tracing::info!("Cache hit for request {}", key);
+            let mut usage = response.usage.take().unwrap_or_default();
+            usage.cost_usd = 0.0;
+            usage.cache_hit = true;
+            response.usage = Some(usage);
+            return response;
+        }
+        let response = self.inner.call_with_options(history, options).await;
+        // failed calls come back without content or tool calls, never cache those
+        if response.content.is_some() || response.tool_calls.is_some() {
+            self.store(key, &response);
+        }
+        response
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn system_prompt(&self) -> &str {
+        self.inner.system_prompt()
+    }
+
+    fn tool_schemas(&self) -> &[Value] {
+        self.inner.tool_schemas()
+    }
+
+    fn default_options(&self) -> GenerationOptions {
+        self.inner.default_options()
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.inner.supports_json_schema()
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::schema::Usage;
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+    // bills every call and counts them, the temperature is part of the request key
+    struct BilledModel {
+        calls: Arc<AtomicUsize>,
+        temperature: Option<f32>,
+    }
+
+    #[async_trait]
+    impl BaseModel for BilledModel {
+        async fn call(&self, user_prompt: &Message) -> LLMResponse {
+            self.call_with_history(vec![user_prompt]).await
+        }
+
+        async fn call_with_history(&self, history: Vec<&Message>) -> LLMResponse {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            LLMResponse {
+                content: Some(format!("echo {}", history.last().unwrap().content)),
+                reasoning_content: None,
+                usage: Some(Usage { prompt_tokens: 10, completion_tokens: 5, total_tokens: 15, cost_usd: 0.01, cache_hit: false }),
+                tool_calls: None,
+            }
+        }
+
+        fn model_name(&self) -> &str {
+            "priced"
+        }
+
+        fn default_options(&self) -> GenerationOptions {
+            GenerationOptions { temperature: self.temperature, ..Default::default() }
+        }
+    }
+
+    fn cache_config(capacity: usize, ttl_secs: Option<u64>, dir: Option<&str>) -> CacheConfig {
+        CacheConfig { enabled: true, ttl_secs, capacity, dir: dir.map(String::from) }
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit_is_free() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let model = CachedModel::new(Box::new(BilledModel { calls: calls.clone(), temperature: None }), &cache_config(8, None, None));
+        let first = model.call(&Message::user("hello")).await;
+        let second = model.call(&Message::user("hello")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first.content, second.content);
+        assert!(!first.usage.unwrap().cache_hit);
+        let usage = second.usage.unwrap();
+        assert!(usage.cache_hit);
+        assert_eq!(usage.cost_usd, 0.0);
+        assert_eq!(usage.total_tokens, 15);
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_and_eviction() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let model = CachedModel::new(Box::new(BilledModel { calls: calls.clone(), temperature: Some(0.2) }), &cache_config(1, None, None));
+        model.call(&Message::user("a")).await;
+        model.call(&Message::user("b")).await;
+        model.call(&Message::user("a")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(model.len(), 1);
+
+        // a different temperature is a different request, whether it comes from the model or the call
+        let other = CachedModel::new(Box::new(BilledModel { calls: calls.clone(), temperature: Some(0.9) }), &cache_config(8, None, None));
+        other.call(&Message::user("a")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        other.call_with_options(vec![&Message::user("a")], &GenerationOptions::new().temperature(0.2)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+        other.call_with_options(vec![&Message::user("a")], &GenerationOptions::new().temperature(0.9)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_cache_ttl_and_disk() {
+        let dir = "./workspace_test/cache_ttl_and_disk";
+        let _ = fs::remove_dir_all(dir);
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let expired = CachedModel::new(Box::new(BilledModel { calls: calls.clone(), temperature: None }), &cache_config(8, Some(0), None));
+        expired.call(&Message::user("hello")).await;
+        expired.call(&Message::user("hello")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let writer = CachedModel::new(Box::new(BilledModel { calls: calls.clone(), temperature: None }), &cache_config(8, Some(3600), Some(dir)));
+        writer.call(&Message::user("hello")).await;
+        let reader = CachedModel::new(Box::new(BilledModel { calls: calls.clone(), temperature: None }), &cache_config(8, Some(3600), Some(dir)));
+        let response = reader.call(&Message::user("hello")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(response.usage.unwrap().cache_hit);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
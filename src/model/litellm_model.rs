@@ -1,83 +1,97 @@
 use serde_json::{json, Value};
+use std::{collections::HashMap, sync::{Arc, Mutex}};
 use anyhow::Context;
 use async_trait::async_trait;
-use llm::{FunctionCall, LLMProvider, ToolCall, builder::{FunctionBuilder, LLMBackend, LLMBuilder}, chat::ChatMessage};
+use tracing::Instrument;
+use llm::{FunctionCall, LLMProvider, ToolCall, builder::{FunctionBuilder, LLMBuilder}, chat::{ChatMessage, StructuredOutputFormat}};
 use crate::{config::config::{ModelConfig}, 
-            model::{base::BaseModel},
+            model::{base::BaseModel, options::{GenerationOptions, ReasoningEffort, ResponseFormat}},
             model::schema::{LLMResponse, Message, Role, Usage}};
 
 
+// distinct per-call options a model keeps providers for, e.g. one per structured output type
+const MAX_CACHED_PROVIDERS: usize = 16;
+
 pub struct LitellmModel{
     pub model_name: String,
-    llm: Box<dyn LLMProvider>,
+    system_prompt: String,
+    tool_schemas: Vec<Value>,
+    settings: ModelConfig,
+    defaults: GenerationOptions,
+    llm: Arc<dyn LLMProvider>,
+    // providers for options other than the defaults, keyed by the options as JSON
+    providers: Mutex<HashMap<String, Arc<dyn LLMProvider>>>,
     cost_per_input_token: f64,
     cost_per_output_token: f64,
 }
 
 impl LitellmModel {
     pub fn new(model_name:&str, settings: &ModelConfig, system_prompt: &str) -> Self {
-        let api_key = settings.api_key.as_str();
-
-        let mut llm_builder = LLMBuilder::new()
-                                .backend(LLMBackend::OpenAI)
-                                .api_key(api_key)
-                                .model(model_name);
-        
-        if system_prompt.len() > 0 {
-            llm_builder = llm_builder.system(system_prompt);
-        }
+        Self::new_with_tools(model_name, settings, system_prompt, Vec::new())
+    }
 
-        if let Some(base_url) = settings.base_url.as_deref() {
-            llm_builder = llm_builder.base_url(base_url);
-        }
-        if let Some(cost) = &settings.cost{
-            llm_builder = llm_builder.max_tokens(cost.max_tokens as u32);
-        }
-        if let Some(temperature) = settings.temperature {
-            llm_builder = llm_builder.temperature(temperature);
-        }
-        if let Some(top_p) = settings.top_p {
-            llm_builder = llm_builder.top_p(top_p);
-        }
-        
-        let llm = llm_builder
-                                .build()
-                                .with_context(|| format!("Failed to build LLM model: {}", model_name)).unwrap();
+    pub fn new_with_tools(model_name:&str, settings: &ModelConfig, system_prompt: &str, functions: Vec<Value>) -> Self {
+        let defaults = settings.generation_options();
+        let llm = Self::build_llm(model_name, settings, system_prompt, &functions, &defaults);
         LitellmModel {
             model_name: model_name.to_string(),
-            llm: llm,
+            system_prompt: system_prompt.to_string(),
+            tool_schemas: functions,
+            settings: settings.clone(),
+            defaults,
+            llm: Arc::from(llm),
+            providers: Mutex::new(HashMap::new()),
             cost_per_input_token: if let Some(cost) = &settings.cost { cost.input_cost_per_token } else { 0.0 },
             cost_per_output_token: if let Some(cost) = &settings.cost { cost.output_cost_per_token } else { 0.0 },
         }
     }
 
-    pub fn new_with_tools(model_name:&str, settings: &ModelConfig, system_prompt: &str, functions: Vec<Value>) -> Self {
-        let api_key = settings.api_key.as_str();
-
+    // the llm crate fixes the generation settings when the provider is built
+    fn build_llm(model_name: &str, settings: &ModelConfig, system_prompt: &str, functions: &[Value], options: &GenerationOptions) -> Box<dyn LLMProvider> {
         let mut llm_builder = LLMBuilder::new()
-                                .backend(LLMBackend::OpenAI)
-                                .api_key(api_key)
+                                .backend(settings.backend())
+                                .api_key(settings.api_key.as_str())
                                 .model(model_name);
         
-        if system_prompt.len() > 0 {
+        if !system_prompt.is_empty() {
             llm_builder = llm_builder.system(system_prompt);
         }
 
         if let Some(base_url) = settings.base_url.as_deref() {
             llm_builder = llm_builder.base_url(base_url);
         }
-        if let Some(cost) = &settings.cost{
-            llm_builder = llm_builder.max_tokens(cost.max_tokens as u32);
+        if let Some(max_tokens) = options.max_output_tokens {
+            llm_builder = llm_builder.max_tokens(max_tokens);
         }
-        if let Some(temperature) = settings.temperature {
+        if let Some(temperature) = options.temperature {
             llm_builder = llm_builder.temperature(temperature);
         }
-        if let Some(top_p) = settings.top_p {
+        if let Some(top_p) = options.top_p {
             llm_builder = llm_builder.top_p(top_p);
         }
+        if let Some(effort) = options.reasoning_effort {
+            llm_builder = llm_builder.reasoning_effort(match effort {
+                ReasoningEffort::Low => llm::chat::ReasoningEffort::Low,
+                ReasoningEffort::Medium => llm::chat::ReasoningEffort::Medium,
+                ReasoningEffort::High => llm::chat::ReasoningEffort::High,
+            });
+        }
+        if let Some(ResponseFormat::JsonSchema { name, schema, strict }) = options.response_format.as_ref() {
+            llm_builder = llm_builder.schema(StructuredOutputFormat {
+                name: name.clone(),
+                description: None,
+                schema: Some(schema.clone()),
+                strict: Some(*strict),
+            });
+        }
+        if let Some(extra_body) = options.extra_body() {
+            llm_builder = llm_builder.extra_body(extra_body);
+        }
         
-        tracing::debug!("Adding functions to LLM: {:?}", functions);
-        for func in functions {
+        if !functions.is_empty() {
+            tracing::debug!("Adding functions to LLM: {:?}", functions);
+        }
+        for func in functions.iter() {
             let func_name = func.get("name").and_then(|v| v.as_str()).unwrap_or("");
             let func_description = func.get("description").and_then(|v| v.as_str()).unwrap_or("");
             let parameters = func.get("parameters").cloned().unwrap_or(Value::Null);
@@ -85,15 +99,9 @@ impl LitellmModel {
             llm_builder = llm_builder.function(function_builder);
         }
 
-        let llm = llm_builder
-                                .build()
-                                .with_context(|| format!("Failed to build LLM model: {}", model_name)).unwrap();
-        LitellmModel {
-            model_name: model_name.to_string(),
-            llm: llm,
-            cost_per_input_token: if let Some(cost) = &settings.cost { cost.input_cost_per_token } else { 0.0 },
-            cost_per_output_token: if let Some(cost) = &settings.cost { cost.output_cost_per_token } else { 0.0 },
-        }
+        llm_builder
+            .build()
+            .with_context(|| format!("Failed to build LLM model: {}", model_name)).unwrap()
     }
 
     fn build_message(&self, msg: &Message) -> ChatMessage {
@@ -125,8 +133,52 @@ impl LitellmModel {
         }
     }
 
-    pub async fn _do_call(&self, messages: &Vec<ChatMessage>) -> LLMResponse {
-        match self.llm.chat(messages).await {
+    pub async fn _do_call(&self, messages: &[ChatMessage]) -> LLMResponse {
+        self._do_call_with_options(messages, &GenerationOptions::default()).await
+    }
+
+    /// The provider for `options`, built once per distinct set of options and then reused
+    fn provider(&self, options: &GenerationOptions) -> Arc<dyn LLMProvider> {
+        if *options == self.defaults {
+            return self.llm.clone();
+        }
+        let key = serde_json::to_string(options).unwrap_or_default();
+        let mut providers = self.providers.lock().unwrap();
+        if let Some(llm) = providers.get(&key) {
+            return llm.clone();
+        }
+        if providers.len() >= MAX_CACHED_PROVIDERS {
+            providers.clear();
+        }
+        let functions: Vec<Value> = self.tool_schemas.iter().chain(options.tools.iter().flatten()).cloned().collect();
+        let llm: Arc<dyn LLMProvider> = Arc::from(Self::build_llm(&self.model_name, &self.settings, &self.system_prompt, &functions, options));
+        providers.insert(key, llm.clone());
+        llm
+    }
+
+    /// Send `messages` with `options` layered on the config defaults
+    pub async fn _do_call_with_options(&self, messages: &[ChatMessage], options: &GenerationOptions) -> LLMResponse {
+        let effective = self.defaults.merged_with(options);
+        let llm = self.provider(&effective);
+        let span = tracing::info_span!(
+            "chat",
+            otel.name = format!("chat {}", self.model_name),
+            otel.kind = "client",
+            otel.status_code = tracing::field::Empty,
+            gen_ai.operation.name = "chat",
+            gen_ai.system = self.settings.gen_ai_system(),
+            gen_ai.request.model = self.model_name.as_str(),
+            gen_ai.request.temperature = effective.temperature,
+            gen_ai.request.top_p = effective.top_p,
+            gen_ai.request.max_tokens = effective.max_output_tokens,
+            gen_ai.request.seed = effective.seed,
+            gen_ai.usage.input_tokens = tracing::field::Empty,
+            gen_ai.usage.output_tokens = tracing::field::Empty,
+            r_agent.cost_usd = tracing::field::Empty,
+        );
+        let result = llm.chat(messages).instrument(span.clone()).await;
+        let _enter = span.enter();
+        match result {
             Ok(response) => {
                 tracing::debug!("LLM Response: {:?}", response.text());
                 tracing::debug!("Usage: {:?}", response.usage());
@@ -140,16 +192,23 @@ impl LitellmModel {
                             completion_tokens: usage.completion_tokens,
                             total_tokens: usage.total_tokens,
                             cost_usd: (self.cost_per_input_token * usage.prompt_tokens as f64 + self.cost_per_output_token * usage.completion_tokens as f64),
+                            cache_hit: false,
                         })
                     } else {
                         None
                     },
                     tool_calls: response.tool_calls()
                 };
+                if let Some(usage) = &ret.usage {
+                    span.record("gen_ai.usage.input_tokens", usage.prompt_tokens);
+                    span.record("gen_ai.usage.output_tokens", usage.completion_tokens);
+                    span.record("r_agent.cost_usd", usage.cost_usd);
+                }
                 tracing::info!("{}", ret);
                 ret
             },
             Err(e) => {
+                span.record("otel.status_code", "ERROR");
                 tracing::error!("Error during LLM call: {}", e);
                 LLMResponse { 
                     content: None,
@@ -165,8 +224,7 @@ impl LitellmModel {
 #[async_trait]
 impl BaseModel for LitellmModel {
     async fn call(&self, user_prompt: &Message) -> LLMResponse {
-        let mut history = Vec::new();
-        history.push(user_prompt);
+        let history = vec![user_prompt];
         tracing::info!("Calling LLM with prompt: {}", user_prompt.content);
         self.call_with_history(history).await
     }
@@ -175,15 +233,43 @@ impl BaseModel for LitellmModel {
             &self,
             history: Vec<&Message>,
         ) -> LLMResponse {
+        self.call_with_options(history, &GenerationOptions::default()).await
+    }
+
+    async fn call_with_options(
+            &self,
+            history: Vec<&Message>,
+            options: &GenerationOptions,
+        ) -> LLMResponse {
         let mut messages = Vec::new();
         for msg in history {
-            let chat_msg = self.build_message(&msg);
+            let chat_msg = self.build_message(msg);
             messages.push(chat_msg);
         }
         // let user_msg = self.build_message(&Role::USER, user_prompt);
         // messages.push(user_msg);
         tracing::info!("Calling LLM with messages: {:#?}", messages);
-        self._do_call(&messages).await
+        self._do_call_with_options(&messages, options).await
+    }
+
+    fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    fn system_prompt(&self) -> &str {
+        &self.system_prompt
+    }
+
+    fn tool_schemas(&self) -> &[Value] {
+        &self.tool_schemas
+    }
+
+    fn default_options(&self) -> GenerationOptions {
+        self.defaults.clone()
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        matches!(self.settings.provider.as_deref(), None | Some("openai") | Some("openai_compatible"))
     }
 }
 
@@ -194,7 +280,20 @@ mod tests {
     use std::vec;
     use super::*;
     use crate::config::config::load_config;
-    
+    use crate::test_support::{offline_config, MODEL};
+
+    #[test]
+    fn test_providers_are_reused() {
+        let config = offline_config();
+        let model = LitellmModel::new(MODEL, config.models.get(MODEL).unwrap(), "");
+        assert!(Arc::ptr_eq(&model.provider(&model.defaults), &model.llm));
+        let options = model.defaults.merged_with(&GenerationOptions::new().temperature(0.0));
+        let first = model.provider(&options);
+        assert!(Arc::ptr_eq(&first, &model.provider(&options)));
+        assert!(!Arc::ptr_eq(&first, &model.provider(&model.defaults.merged_with(&GenerationOptions::new().seed(1)))));
+        assert_eq!(model.providers.lock().unwrap().len(), 2);
+    }
+
     #[tokio::test]
     async fn test_llm() {
         let config = load_config(None);
@@ -226,11 +325,9 @@ mod tests {
         let model_name = "gpt-4o-mini";
         let model_config = config.models.get(model_name).unwrap();
         let litellm_model = LitellmModel::new(model_name, model_config, "");
-        let history = vec![
-            Message::user("Hello, how about the weather of NY today"),
+        let history = [Message::user("Hello, how about the weather of NY today"),
             Message::assistant("The weather in NY today is sunny with a high of 75°F.", None),
-            Message::user("Can you give me a summary of our previous conversation?")
-        ];
+            Message::user("Can you give me a summary of our previous conversation?")];
         let history_refs: Vec<&Message> = history.iter().collect();
         let out = litellm_model.call_with_history(history_refs).await;
         println!("\nOutput: {:?}", out);
@@ -267,11 +364,9 @@ mod tests {
         let output = out.content.unwrap_or("No content".to_string());
         let tool_calls = out.tool_calls.unwrap();
         let result = "current weather in Boston is 68°F and sunny";
-        let prompts = vec![
-            Message::user("What's the weather like in Boston?"),
+        let prompts = [Message::user("What's the weather like in Boston?"),
             Message::assistant(&output, Some(tool_calls.clone())),
-            Message::tool(result, Some(tool_calls.clone()), Some(tool_calls[0].id.clone()))
-        ];
+            Message::tool(result, Some(tool_calls.clone()), Some(tool_calls[0].id.clone()))];
         let prompt_refs: Vec<&Message> = prompts.iter().collect();
         let out_with_history = litellm_model.call_with_history(prompt_refs).await;
         println!("\nOutput with tools: {:?}", out_with_history);
@@ -309,11 +404,9 @@ mod tests {
         let output = out.content.unwrap_or("No content".to_string());
         let tool_calls = out.tool_calls.unwrap();
         let result = "11000";
-        let prompts = vec![
-            Message::user(user_prompt),
+        let prompts = [Message::user(user_prompt),
             Message::assistant(&output, Some(tool_calls.clone())),
-            Message::tool(result, Some(tool_calls.clone()), Some(tool_calls[0].id.clone()))
-        ];
+            Message::tool(result, Some(tool_calls.clone()), Some(tool_calls[0].id.clone()))];
         let prompt_refs: Vec<&Message> = prompts.iter().collect();
         let out_with_history = litellm_model.call_with_history(prompt_refs).await;
         println!("\nOutput with tools: {:?}", out_with_history);
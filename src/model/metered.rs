@@ -0,0 +1,94 @@
+use serde_json::Value;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use crate::model::{base::BaseModel, options::GenerationOptions, schema::{LLMResponse, Message, Usage}};
+
+/// Metered model
+/// Adds up the usage of every call made through it, e.g. to account for planner or critic calls of an agent.
+pub struct MeteredModel {
+    inner: Box<dyn BaseModel>,
+    usage: Mutex<Usage>,
+}
+
+impl MeteredModel {
+    pub fn new(inner: Box<dyn BaseModel>) -> Self {
+        MeteredModel { inner, usage: Mutex::new(Usage::default()) }
+    }
+
+    /// Usage since creation or the last `take_usage`
+    pub fn usage(&self) -> Usage {
+        self.usage.lock().unwrap().clone()
+    }
+
+    pub fn take_usage(&self) -> Usage {
+        std::mem::take(&mut *self.usage.lock().unwrap())
+    }
+
+    /// Replace the wrapped model, the usage so far is kept
+    pub fn map_inner<F: FnOnce(Box<dyn BaseModel>) -> Box<dyn BaseModel>>(self, f: F) -> Self {
+        MeteredModel { inner: f(self.inner), usage: self.usage }
+    }
+}
+
+#[async_trait]
+impl BaseModel for MeteredModel {
+    async fn call(&self, user_prompt: &Message) -> LLMResponse {
+        self.call_with_history(vec![user_prompt]).await
+    }
+
+    async fn call_with_history(
+            &self,
+            history: Vec<&Message>,
+        ) -> LLMResponse {
+        self.call_with_options(history, &GenerationOptions::default()).await
+    }
+
+    async fn call_with_options(
+            &self,
+            history: Vec<&Message>,
+            options: &GenerationOptions,
+        ) -> LLMResponse {
+        let response = self.inner.call_with_options(history, options).await;
+        if let Some(usage) = response.usage.as_ref() {
+            self.usage.lock().unwrap().accumulate(usage);
+        }
+        response
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn system_prompt(&self) -> &str {
+        self.inner.system_prompt()
+    }
+
+    fn tool_schemas(&self) -> &[Value] {
+        self.inner.tool_schemas()
+    }
+
+    fn default_options(&self) -> GenerationOptions {
+        self.inner.default_options()
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.inner.supports_json_schema()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{text, usage, FnModel};
+
+    #[tokio::test]
+    async fn test_metered_usage() {
+        let model = MeteredModel::new(Box::new(FnModel(|_: &[&Message]| LLMResponse { usage: Some(usage(15)), ..text("ok") })));
+        model.call(&Message::user("a")).await;
+        model.call(&Message::user("b")).await;
+        assert_eq!(model.usage().total_tokens, 30);
+        assert_eq!(model.take_usage().prompt_tokens, 30);
+        assert_eq!(model.usage().total_tokens, 0);
+    }
+}
@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Any valid JSON object
+    JsonObject,
+    /// JSON matching `schema`, enforced by the provider when `strict` is set
+    JsonSchema {
+        name: String,
+        schema: Value,
+        #[serde(default)]
+        strict: bool,
+    },
+}
+
+/// Sampling and output settings of a single request.
+/// Unset fields fall back to the layer below: per call over per agent over the model config.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    // function schemas offered in addition to the tools of the model, e.g. the `final_answer` tool of a typed run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Value>>,
+}
+
+impl GenerationOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn max_output_tokens(mut self, max_output_tokens: u32) -> Self {
+        self.max_output_tokens = Some(max_output_tokens);
+        self
+    }
+
+    pub fn stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn reasoning_effort(mut self, reasoning_effort: ReasoningEffort) -> Self {
+        self.reasoning_effort = Some(reasoning_effort);
+        self
+    }
+
+    pub fn response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    pub fn tools(mut self, tools: Vec<Value>) -> Self {
+        self.tools = Some(tools);
+        self
+    }
+
+    /// `overrides` wins field by field, unset fields keep the value of `self`
+    pub fn merged_with(&self, overrides: &GenerationOptions) -> GenerationOptions {
+        GenerationOptions {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_output_tokens: overrides.max_output_tokens.or(self.max_output_tokens),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+            seed: overrides.seed.or(self.seed),
+            reasoning_effort: overrides.reasoning_effort.or(self.reasoning_effort),
+            response_format: overrides.response_format.clone().or_else(|| self.response_format.clone()),
+            tools: overrides.tools.clone().or_else(|| self.tools.clone()),
+        }
+    }
+
+    /// Request fields the llm builder has no setter for, sent as-is in the OpenAI request body
+    pub(crate) fn extra_body(&self) -> Option<Value> {
+        let mut body = Map::new();
+        if let Some(stop) = self.stop.as_ref() {
+            body.insert("stop".to_string(), json!(stop));
+        }
+        if let Some(seed) = self.seed {
+            body.insert("seed".to_string(), json!(seed));
+        }
+        if let Some(ResponseFormat::JsonObject) = self.response_format {
+            body.insert("response_format".to_string(), json!({"type": "json_object"}));
+        }
+        if body.is_empty() { None } else { Some(Value::Object(body)) }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_options() {
+        let defaults = GenerationOptions::new().temperature(0.7).top_p(0.9).max_output_tokens(1024);
+        let call = GenerationOptions::new().temperature(0.0).seed(7).stop(vec!["END".to_string()]);
+        let merged = defaults.merged_with(&call);
+        assert_eq!(merged.temperature, Some(0.0));
+        assert_eq!(merged.top_p, Some(0.9));
+        assert_eq!(merged.max_output_tokens, Some(1024));
+        assert_eq!(merged.seed, Some(7));
+        assert_eq!(merged.extra_body(), Some(json!({"stop": ["END"], "seed": 7})));
+        assert_eq!(defaults.merged_with(&GenerationOptions::default()), defaults);
+    }
+
+    #[test]
+    fn test_options_from_yaml() {
+        let options: GenerationOptions = serde_yml::from_str("temperature: 0.2\nreasoning_effort: high\nresponse_format:\n  type: json_object\n").unwrap();
+        assert_eq!(options.reasoning_effort, Some(ReasoningEffort::High));
+        assert_eq!(options.response_format, Some(ResponseFormat::JsonObject));
+    }
+}
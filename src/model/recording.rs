@@ -0,0 +1,262 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Mutex}};
+use async_trait::async_trait;
+use crate::model::{base::BaseModel, options::GenerationOptions, schema::{LLMResponse, Message, ModelRequest}};
+
+// 2: requests carry temperature and top_p
+// 3: requests carry the effective `GenerationOptions`
+pub const CASSETTE_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordMode {
+    /// Forward every request to the inner model and store the response
+    Record,
+    /// Serve responses from the cassette only, unrecorded requests are a failure
+    Replay,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: ModelRequest,
+    pub response: LLMResponse,
+}
+
+/// Request/response pairs keyed by `ModelRequest::key()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cassette {
+    pub version: u32,
+    pub interactions: BTreeMap<String, Interaction>,
+}
+
+impl Default for Cassette {
+    fn default() -> Self {
+        Cassette { version: CASSETTE_VERSION, interactions: BTreeMap::new() }
+    }
+}
+
+impl Cassette {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read cassette file: {:?}", path))?;
+        let cassette: Cassette = serde_json::from_str(&content).with_context(|| format!("Failed to parse cassette file: {:?}", path))?;
+        anyhow::ensure!(cassette.version == CASSETTE_VERSION, "Unsupported cassette version {} in {:?}", cassette.version, path);
+        Ok(cassette)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| format!("Failed to create cassette directory: {:?}", parent))?;
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(path, content).with_context(|| format!("Failed to write cassette file: {:?}", path))
+    }
+}
+
+/// Recording model
+/// Wraps another model and records every request/response pair into a cassette file,
+/// or replays the cassette without touching the inner model so runs can be reproduced offline.
+/// Recordings are written by `save` and when the model is dropped.
+pub struct RecordingModel {
+    inner: Box<dyn BaseModel>,
+    mode: RecordMode,
+    path: PathBuf,
+    cassette: Mutex<Cassette>,
+    // interactions recorded since the latest save
+    unsaved: AtomicBool,
+}
+
+impl RecordingModel {
+    /// Record into `path`, interactions already in the file are kept
+    pub fn record(inner: Box<dyn BaseModel>, path: &str) -> anyhow::Result<Self> {
+        let path = PathBuf::from(path);
+        let cassette = if path.exists() {
+            Cassette::load(&path)?
+        } else {
+            Cassette::default()
+        };
+        Ok(RecordingModel { inner, mode: RecordMode::Record, path, cassette: Mutex::new(cassette), unsaved: AtomicBool::new(false) })
+    }
+
+    /// Replay from `path`. The inner model only provides the model name, system prompt and tools of the requests.
+    pub fn replay(inner: Box<dyn BaseModel>, path: &str) -> anyhow::Result<Self> {
+        let path = PathBuf::from(path);
+        let cassette = Cassette::load(&path)?;
+        Ok(RecordingModel { inner, mode: RecordMode::Replay, path, cassette: Mutex::new(cassette), unsaved: AtomicBool::new(false) })
+    }
+
+    /// Write the interactions recorded so far to the cassette file
+    pub fn save(&self) -> anyhow::Result<()> {
+        if !self.unsaved.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let cassette = self.cassette.lock().unwrap().clone();
+        cassette.save(&self.path).inspect_err(|_| self.unsaved.store(true, Ordering::SeqCst))
+    }
+
+    pub fn mode(&self) -> RecordMode {
+        self.mode
+    }
+
+    pub fn interaction_count(&self) -> usize {
+        self.cassette.lock().unwrap().interactions.len()
+    }
+}
+
+#[async_trait]
+impl BaseModel for RecordingModel {
+    async fn call(&self, user_prompt: &Message) -> LLMResponse {
+        self.call_with_history(vec![user_prompt]).await
+    }
+
+    async fn call_with_history(
+            &self,
+            history: Vec<&Message>,
+        ) -> LLMResponse {
+        self.call_with_options(history, &GenerationOptions::default()).await
+    }
+
+    async fn call_with_options(
+            &self,
+            history: Vec<&Message>,
+            options: &GenerationOptions,
+        ) -> LLMResponse {
+        let request = ModelRequest::new(self, &history, options);
+        let key = request.key();
+        match self.mode {
+            RecordMode::Replay => {
+                // a miss panics, the lock must be released by then or it poisons the cassette for later calls
+                let recorded = self.cassette.lock().unwrap().interactions.get(&key).map(|interaction| interaction.response.clone());
+                match recorded {
+                    Some(response) => {
+                        tracing::debug!("Replaying recorded response {} from {:?}", key, self.path);
+                        response
+                    },
+                    None => {
+                        tracing::error!("No recorded response for request {} in {:?}: {:?}", key, self.path, request);
+                        panic!("No recorded response for request {} in cassette {:?}", key, self.path);
+                    }
+                }
+            },
+            RecordMode::Record => {
+                let response = self.inner.call_with_options(history, options).await;
+                // failed calls come back without content or tool calls, a replay should not repeat them
+                if response.content.is_some() || response.tool_calls.is_some() {
+                    self.cassette.lock().unwrap().interactions.insert(key, Interaction { request, response: response.clone() });
+                    self.unsaved.store(true, Ordering::SeqCst);
+                }
+                response
+            }
+        }
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn system_prompt(&self) -> &str {
+        self.inner.system_prompt()
+    }
+
+    fn tool_schemas(&self) -> &[Value] {
+        self.inner.tool_schemas()
+    }
+
+    fn default_options(&self) -> GenerationOptions {
+        self.inner.default_options()
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.inner.supports_json_schema()
+    }
+}
+
+impl Drop for RecordingModel {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            tracing::error!("Failed to save cassette {:?}: {:#}", self.path, e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicUsize, Arc};
+    use crate::test_support::{echo_model, FnModel};
+
+    struct CountingModel {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BaseModel for CountingModel {
+        async fn call(&self, user_prompt: &Message) -> LLMResponse {
+            self.call_with_history(vec![user_prompt]).await
+        }
+
+        async fn call_with_history(&self, history: Vec<&Message>) -> LLMResponse {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            LLMResponse {
+                content: Some(format!("response {} to {}", n, history.last().unwrap().content)),
+                reasoning_content: None,
+                usage: None,
+                tool_calls: None,
+            }
+        }
+
+        fn model_name(&self) -> &str {
+            "counting"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let path = "./workspace_test/cassettes/record_then_replay.json";
+        let _ = fs::remove_file(path);
+
+        let recorder = RecordingModel::record(Box::new(CountingModel { calls: AtomicUsize::new(0) }), path).unwrap();
+        let first = recorder.call(&Message::user("hello")).await;
+        let second = recorder.call(&Message::user("  world ")).await;
+        assert_eq!(recorder.interaction_count(), 2);
+        assert!(!Path::new(path).exists());
+        drop(recorder);
+
+        let player = Arc::new(RecordingModel::replay(Box::new(CountingModel { calls: AtomicUsize::new(100) }), path).unwrap());
+        assert_eq!(player.call(&Message::user("hello")).await.content, first.content);
+        // a miss fails its own call only
+        let missing = player.clone();
+        assert!(tokio::spawn(async move { missing.call(&Message::user("never recorded")).await }).await.is_err());
+        assert_eq!(player.call(&Message::user("world")).await.content, second.content);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_calls_are_not_recorded() {
+        let path = "./workspace_test/cassettes/failed_calls.json";
+        let _ = fs::remove_file(path);
+        let failing = FnModel(|_: &[&Message]| LLMResponse { content: None, reasoning_content: None, usage: None, tool_calls: None });
+        let recorder = RecordingModel::record(Box::new(failing), path).unwrap();
+        recorder.call(&Message::user("hello")).await;
+        assert_eq!(recorder.interaction_count(), 0);
+        drop(recorder);
+        assert!(!Path::new(path).exists());
+
+        // a corrupt cassette is an error, not a panic
+        fs::write(path, "{").unwrap();
+        assert!(RecordingModel::replay(Box::new(echo_model()), path).is_err());
+        assert!(RecordingModel::record(Box::new(echo_model()), path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "No recorded response")]
+    async fn test_replay_unrecorded_request() {
+        let path = "./workspace_test/cassettes/replay_unrecorded.json";
+        Cassette::default().save(Path::new(path)).unwrap();
+        let player = RecordingModel::replay(Box::new(CountingModel { calls: AtomicUsize::new(0) }), path).unwrap();
+        fs::remove_file(path).unwrap();
+        player.call(&Message::user("never recorded")).await;
+    }
+}
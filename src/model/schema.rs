@@ -1,7 +1,11 @@
 use std::fmt;
 use llm::ToolCall;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use crate::model::{base::BaseModel, options::GenerationOptions};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: Role,
     pub content: String,
@@ -19,7 +23,7 @@ impl fmt::Display for Message {
             Some(id) => id.clone(),
             None => "None".to_string(),
         };
-        write!(f, "{}: content: {}, tool_calls: {}, tool_call_id: {}\n", self.role, self.content, tool_calls_str, tool_call_id_str)
+        writeln!(f, "{}: content: {}, tool_calls: {}, tool_call_id: {}", self.role, self.content, tool_calls_str, tool_call_id_str)
     }
 }
 
@@ -61,7 +65,8 @@ impl Message {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Role{
     SYSTEM,
     USER,
@@ -81,7 +86,7 @@ impl fmt::Display for Role {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMResponse {
     pub content: Option<String>,
     pub reasoning_content: Option<String>,
@@ -117,18 +122,81 @@ impl fmt::Display for LLMResponse {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage{
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
     pub cost_usd: f64,
+    // the response was served from the cache, nothing was billed
+    #[serde(default)]
+    pub cache_hit: bool,
+}
+
+impl Usage {
+    /// Add another usage record onto this one, e.g. to total a whole run.
+    pub fn accumulate(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cost_usd += other.cost_usd;
+    }
 }
 
 impl fmt::Display for Usage {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "prompt_tokens: {}, completion_tokens: {}, total_tokens: {}, cost_usd: {}", self.prompt_tokens, self.completion_tokens, self.total_tokens, self.cost_usd)
+        write!(f, "prompt_tokens: {}, completion_tokens: {}, total_tokens: {}, cost_usd: {}, cache_hit: {}", self.prompt_tokens, self.completion_tokens, self.total_tokens, self.cost_usd, self.cache_hit)
     }
 }
 
 
+/// Normalized form of a request sent to a model.
+/// Two requests with the same `key()` are expected to produce the same response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelRequest {
+    pub model: String,
+    pub system_prompt: String,
+    pub tools: Vec<Value>,
+    // the effective options, model defaults merged with the per-call options
+    pub options: GenerationOptions,
+    pub messages: Vec<Message>,
+}
+
+impl ModelRequest {
+    pub fn new<B: BaseModel + ?Sized>(model: &B, history: &[&Message], options: &GenerationOptions) -> Self {
+        ModelRequest {
+            model: model.model_name().to_string(),
+            system_prompt: model.system_prompt().trim().to_string(),
+            tools: model.tool_schemas().to_vec(),
+            options: model.default_options().merged_with(options),
+            messages: history.iter().map(|msg| {
+                let mut msg = (*msg).clone();
+                msg.content = msg.content.trim().to_string();
+                msg
+            }).collect(),
+        }
+    }
+
+    /// Hex encoded sha256 of the request with all object keys sorted
+    pub fn key(&self) -> String {
+        let value = serde_json::to_value(self).expect("ModelRequest is always serializable");
+        let digest = Sha256::digest(canonical_json(&value).to_string().as_bytes());
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+fn canonical_json(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let mut sorted = Map::new();
+            for key in keys {
+                sorted.insert(key.clone(), canonical_json(&map[key]));
+            }
+            Value::Object(sorted)
+        },
+        Value::Array(items) => Value::Array(items.iter().map(canonical_json).collect()),
+        other => other.clone(),
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use crate::{model::{base::BaseModel, options::{GenerationOptions, ResponseFormat}, schema::Message},
+            prompt::structured::*};
+
+pub const DEFAULT_STRUCTURED_ATTEMPTS: usize = 3;
+
+/// The model never produced a value of the requested type
+#[derive(Debug, Clone)]
+pub struct StructuredError {
+    pub attempts: usize,
+    // why the last attempt was rejected
+    pub message: String,
+    // the last raw text returned by the model, if any
+    pub last_output: Option<String>,
+}
+
+impl fmt::Display for StructuredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No valid structured output after {} attempt(s): {}", self.attempts, self.message)
+    }
+}
+
+impl std::error::Error for StructuredError {}
+
+/// Typed output on top of any `BaseModel`.
+/// The JSON schema of `T` is enforced by the provider when `supports_json_schema()`, otherwise it is put into the prompt.
+/// JSON is extracted from the reply and repaired, on failure the error is fed back and the call retried.
+#[async_trait]
+pub trait StructuredOutput: BaseModel {
+    async fn call_structured<T: DeserializeOwned + JsonSchema + Send>(
+        &self,
+        history: Vec<&Message>,
+        options: &GenerationOptions,
+    ) -> Result<T, StructuredError> {
+        self.call_structured_with_attempts(history, options, DEFAULT_STRUCTURED_ATTEMPTS).await
+    }
+
+    async fn call_structured_with_attempts<T: DeserializeOwned + JsonSchema + Send>(
+        &self,
+        history: Vec<&Message>,
+        options: &GenerationOptions,
+        max_attempts: usize,
+    ) -> Result<T, StructuredError> {
+        let schema = schemars::schema_for!(T).to_value();
+        let mut options = options.clone();
+        let mut messages: Vec<Message> = history.into_iter().cloned().collect();
+        if self.supports_json_schema() {
+            options.response_format = Some(ResponseFormat::JsonSchema { name: schema_name::<T>(), schema, strict: false });
+        } else {
+            let schema_str = serde_json::to_string_pretty(&schema).unwrap_or_default();
+            messages.push(Message::user(&STRUCTURED_OUTPUT_PROMPT.replace("{schema}", &schema_str)));
+        }
+
+        let mut error = StructuredError { attempts: 0, message: "no attempt was made".to_string(), last_output: None };
+        for attempt in 1..=max_attempts.max(1) {
+            error.attempts = attempt;
+            let response = self.call_with_options(messages.iter().collect(), &options).await;
+            let Some(text) = response.content else {
+                tracing::error!("Structured call returned no content, attempt {}/{}", attempt, max_attempts);
+                error.message = "the model returned no content".to_string();
+                continue;
+            };
+            match parse_json::<T>(&text) {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    tracing::error!("Failed to parse structured output: {}, attempt {}/{}", e, attempt, max_attempts);
+                    messages.push(Message::assistant(&text, None));
+                    messages.push(Message::user(&STRUCTURED_RETRY_PROMPT.replace("{error}", &e)));
+                    error.message = e;
+                    error.last_output = Some(text);
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+impl<B: BaseModel + ?Sized> StructuredOutput for B {}
+
+// providers only accept names made of letters, digits, `_` and `-`
+fn schema_name<T: JsonSchema>() -> String {
+    T::schema_name().chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+/// Parse `T` from a model reply: as is, then the extracted JSON, then the repaired JSON
+pub fn parse_json<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    let first_error = match serde_json::from_str::<T>(text.trim()) {
+        Ok(value) => return Ok(value),
+        Err(e) => e.to_string(),
+    };
+    let Some(extracted) = extract_json(text) else {
+        return Err(format!("no JSON found in the response ({})", first_error));
+    };
+    match serde_json::from_str::<T>(extracted) {
+        Ok(value) => Ok(value),
+        Err(e) => serde_json::from_str::<T>(&repair_json(extracted)).map_err(|_| e.to_string()),
+    }
+}
+
+/// The JSON part of a reply: the first fenced code block if any, then from the first `{`/`[` to the last `}`/`]`
+pub fn extract_json(text: &str) -> Option<&str> {
+    let text = match text.split_once("```") {
+        Some((_, fenced)) => {
+            let fenced = fenced.strip_prefix("json").unwrap_or(fenced);
+            fenced.split_once("```").map(|(block, _)| block).unwrap_or(fenced)
+        },
+        None => text,
+    };
+    let start = text.find(['{', '['])?;
+    let end = text.rfind(['}', ']']).filter(|end| *end > start).map(|end| end + 1).unwrap_or(text.len());
+    Some(text[start..end].trim())
+}
+
+/// Fix the usual mistakes of models: trailing commas, and strings, objects or arrays left open by a cut off reply
+pub fn repair_json(json: &str) -> String {
+    let chars: Vec<char> = json.chars().collect();
+    let mut out = String::with_capacity(json.len());
+    let mut closers = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, &c) in chars.iter().enumerate() {
+        if in_string {
+            out.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {},
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => { closers.pop(); },
+            ',' if chars[i + 1..].iter().find(|c| !c.is_whitespace()).is_none_or(|next| *next == '}' || *next == ']') => continue,
+            _ => {},
+        }
+        out.push(c);
+    }
+    if in_string {
+        out.push('"');
+    }
+    while let Some(closer) = closers.pop() {
+        out.push(closer);
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use crate::test_support::ScriptedModel;
+
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Weather {
+        city: String,
+        celsius: f32,
+    }
+
+    #[test]
+    fn test_extract_and_repair() {
+        let reply = "Sure!\n```json\n{\"city\": \"Oslo\", \"celsius\": 3.5,}\n```\nAnything else?";
+        assert_eq!(parse_json::<Weather>(reply).unwrap(), Weather { city: "Oslo".to_string(), celsius: 3.5 });
+        assert_eq!(repair_json(r#"{"a": [1, 2,], "b": "cut"#), r#"{"a": [1, 2], "b": "cut"}"#);
+        assert!(parse_json::<Weather>("no json here").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_call_structured_retries_with_error() {
+        let model = ScriptedModel::new([r#"{"city": "Oslo"}"#, r#"{"city": "Oslo", "celsius": -2}"#]);
+        let weather: Weather = model.call_structured(vec![&Message::user("weather in Oslo?")], &GenerationOptions::default()).await.unwrap();
+        assert_eq!(weather.celsius, -2.0);
+
+        let requests = model.requests();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].0[1].content.contains("\"celsius\""));
+        assert!(requests[1].0.last().unwrap().content.contains("missing field `celsius`"));
+    }
+
+    #[tokio::test]
+    async fn test_call_structured_uses_json_schema() {
+        let model = ScriptedModel::new(["not json", "still not json"]).with_json_schema();
+        let err = model.call_structured_with_attempts::<Weather>(vec![&Message::user("weather?")], &GenerationOptions::default(), 2).await.unwrap_err();
+        assert_eq!(err.attempts, 2);
+        assert_eq!(err.last_output.as_deref(), Some("still not json"));
+
+        let requests = model.requests();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].0.len(), 1);
+        assert!(matches!(&requests[0].1.response_format, Some(ResponseFormat::JsonSchema { name, .. }) if name == "Weather"));
+    }
+}
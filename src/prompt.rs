@@ -1,2 +1,6 @@
 pub mod agent;
+pub mod group_chat;
+pub mod plan;
+pub mod reflect;
+pub mod structured;
 pub mod summary;
\ No newline at end of file
@@ -13,18 +13,14 @@ For each step, follow this pattern:
 
 ## When to Finish
 
-When you have gathered enough information and can provide a complete answer to the user's question:
-1. Provide your final answer clearly
-2. End your response with the token: {REACT_END_TOKEN}
+{FINISH_INSTRUCTIONS}
 
 ## Important Rules
 
 - Always think before acting
 - Use tools when you need external information or to perform actions
 - Do not make up information - use tools to verify facts
-- When the task is complete, always end with {REACT_END_TOKEN}
-- The {REACT_END_TOKEN} token signals that your reasoning is complete
-
+{FINISH_RULES}
 ## Response Format
 
 Your response should follow this structure:
@@ -34,9 +30,70 @@ Your response should follow this structure:
 **Action**: [Tool call if needed, or skip if no tool is needed]
 
 **Final Answer**: [Your complete answer when ready]
-{REACT_END_TOKEN}
+{FINISH_FORMAT}"#;
+
+// finish sections of the template, one set per `Termination` strategy
+
+pub const END_TOKEN_FINISH_INSTRUCTIONS: &str = r#"When you have gathered enough information and can provide a complete answer to the user's question:
+1. Provide your final answer clearly
+2. End your response with the token: {REACT_END_TOKEN}"#;
+
+pub const END_TOKEN_FINISH_RULES: &str = r#"- When the task is complete, always end with {REACT_END_TOKEN}
+- The {REACT_END_TOKEN} token signals that your reasoning is complete
 "#;
 
+pub const NO_TOOL_CALLS_FINISH_INSTRUCTIONS: &str = r#"When you have gathered enough information and can provide a complete answer to the user's question, reply with your final answer without calling any tool. A response without tool calls ends the task."#;
+
+pub const NO_TOOL_CALLS_FINISH_RULES: &str = r#"- Only reply without a tool call when your answer is complete
+"#;
+
+pub const FINISH_TOOL: &str = "finish";
+
+pub const FINISH_TOOL_DESCRIPTION: &str = "Give the final answer to the user. Calling this tool ends the task.";
+
+pub const FINISH_TOOL_FINISH_INSTRUCTIONS: &str = r#"When you have gathered enough information and can provide a complete answer to the user's question, call the `finish` tool with your complete answer as `answer`. Calling it ends the task."#;
+
+pub const FINISH_TOOL_FINISH_RULES: &str = r#"- Always end the task by calling the `finish` tool, never just write the answer
+"#;
+
+pub fn react_system_prompt(instructions: &str, rules: &str, format: &str) -> String {
+    REACT_SYSTEM_PROMPT_TEMPLATE.replace("{FINISH_INSTRUCTIONS}", instructions)
+                                .replace("{FINISH_RULES}", rules)
+                                .replace("{FINISH_FORMAT}", format)
+                                .replace("{REACT_END_TOKEN}", REACT_END_TOKEN)
+}
+
 pub static REACT_SYSTEM_PROMPT: Lazy<String> = Lazy::new(|| {
-    REACT_SYSTEM_PROMPT_TEMPLATE.replace("{REACT_END_TOKEN}", REACT_END_TOKEN)
-});
\ No newline at end of file
+    react_system_prompt(END_TOKEN_FINISH_INSTRUCTIONS, END_TOKEN_FINISH_RULES, "{REACT_END_TOKEN}\n")
+});
+
+pub const FINAL_ANSWER_TOOL: &str = "final_answer";
+
+pub const FINAL_ANSWER_TOOL_DESCRIPTION: &str = "Submit the final answer to the user. Calling this tool ends the task.";
+
+pub const TYPED_ANSWER_PROMPT: &str = r#"When you have the complete answer, call the `final_answer` tool with it. The task only ends with that tool call, do not end your response with the end token."#;
+
+pub const FINAL_ANSWER_RETRY_PROMPT: &str = r#"The final answer was rejected: {error}
+Call `final_answer` again with corrected arguments."#;
+
+// result of the tool calls of a response after the call that ended the run
+pub const SKIPPED_TOOL_RESULT: &str = "Not executed, the task already ended.";
+
+// task given to an agent called as a tool, when the caller passes context
+pub const DELEGATED_TASK_PROMPT: &str = r#"Context from the calling agent:
+{context}
+
+Task:
+{task}"#;
+
+pub const HANDOFF_TOOL_PREFIX: &str = "transfer_to_";
+
+pub const HANDOFF_TOOL_DESCRIPTION: &str = "Transfer the conversation to the {agent} agent, who continues helping the user. Use it when the request is outside your expertise. {agent} handles: {description}";
+
+pub const HANDOFF_RESULT: &str = "Transferred the conversation to {agent}.";
+
+pub const HANDOFF_LIMIT_PROMPT: &str = r#"The conversation cannot be transferred again. Answer the user's latest request yourself:
+{prompt}"#;
+
+pub const HANDOFF_PROMPT: &str = r#"{from} transferred the conversation to you. Continue helping the user with their latest request:
+{prompt}"#;
@@ -0,0 +1,17 @@
+// group chat prompt modules
+
+pub const GROUP_CHAT_TURN_PROMPT: &str = r#"You are {name} in a group chat with: {participants}.
+Messages since your last turn:
+{transcript}
+
+It is your turn. Reply with your message to the group only."#;
+
+pub const SPEAKER_SELECTOR_SYSTEM_PROMPT: &str = r#"You moderate a group chat. You pick the participant whose expertise moves the conversation forward the most."#;
+
+pub const SELECT_SPEAKER_PROMPT: &str = r#"Participants:
+{participants}
+
+Conversation so far:
+{transcript}
+
+Who should speak next? Answer with the name of one participant; do not pick {last} again unless nobody else can help."#;
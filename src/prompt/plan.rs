@@ -0,0 +1,42 @@
+// plan-and-execute agent prompt modules
+
+pub const PLANNER_SYSTEM_PROMPT: &str = r#"You are a planner. You break tasks into short, concrete steps for an assistant that executes them one by one, and you keep the plan up to date as results come in."#;
+
+pub const PLAN_PROMPT: &str = r#"Create a plan for the following task. Each step must be a single concrete instruction the assistant can carry out, in execution order. Use as few steps as possible.
+
+The assistant can use these tools:
+{tools}
+
+Task:
+{task}"#;
+
+pub const REVISE_PLAN_PROMPT: &str = r#"Task:
+{task}
+
+Completed steps and their results:
+{completed}
+
+Remaining plan:
+{remaining}
+
+Update the remaining steps based on the results so far: remove steps that are no longer needed, change steps that need to be done differently and add missing ones.
+If the task is already complete, return no remaining steps and give the final answer to the task."#;
+
+pub const EXECUTE_STEP_PROMPT: &str = r#"You are working on the task: {task}
+
+Plan:
+{plan}
+
+Results of the completed steps:
+{completed}
+
+Now carry out step {index} only: {step}
+Answer with the result of this step."#;
+
+pub const PLAN_FINAL_ANSWER_PROMPT: &str = r#"Task:
+{task}
+
+Completed steps and their results:
+{completed}
+
+Write the final answer to the task based on these results."#;
@@ -0,0 +1,25 @@
+// reflective agent prompt modules
+
+pub const CRITIC_SYSTEM_PROMPT: &str = r#"You are a strict reviewer. You check whether an answer fully and correctly solves a task, and when it does not, you explain precisely what is wrong and how to fix it."#;
+
+pub const CRITIQUE_PROMPT: &str = r#"Task:
+{task}
+
+Answer:
+{answer}
+
+{checks}
+Decide whether the answer fully and correctly solves the task. If it does not, write a short critique naming each problem and how to fix it."#;
+
+pub const TEST_RESULTS_PROMPT: &str = r#"Test results:
+{results}
+"#;
+
+pub const REFLECTION_PROMPT: &str = r#"Your previous answer (attempt {round}) did not pass review.
+Reflection:
+{critique}"#;
+
+pub const RETRY_PROMPT: &str = r#"Solve the task again, taking the reflections above into account.
+
+Task:
+{task}"#;
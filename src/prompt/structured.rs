@@ -0,0 +1,10 @@
+// structured output prompt modules
+
+pub const STRUCTURED_OUTPUT_PROMPT: &str = r#"Respond only with a single JSON value that matches the following JSON schema, without any other text:
+```json
+{schema}
+```"#;
+
+pub const STRUCTURED_RETRY_PROMPT: &str = r#"Your previous response could not be used: {error}
+
+Respond again with only the corrected JSON value matching the schema."#;
@@ -67,4 +67,20 @@ Output your compressed summary in the following JSON format:
 ```
 
 Be aggressive in compression but never lose information critical to continuing the task.
-"#;
\ No newline at end of file
+"#;
+
+pub const ENTITY_EXTRACTION_PROMPT: &str = r#"You maintain a store of entities mentioned in a conversation: people, organizations, IDs, preferences, configuration values and similar facts worth remembering.
+
+Known entities:
+{known}
+
+Current facts of the entities mentioned in the new messages:
+{facts}
+
+New messages, oldest first:
+{messages}
+
+Return every entity the new messages add to or change, with its complete, updated list of facts. Reuse the exact name of a known entity when a message refers to it. Drop facts a later message contradicts. Return an empty list when there is nothing worth remembering."#;
+
+pub const ENTITY_CONTEXT_FORMAT: &str = r#"Known facts relevant to the latest message:
+{entities}"#;
@@ -0,0 +1,144 @@
+use anyhow::Context;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry};
+use tracing_appender::{non_blocking::WorkerGuard, rolling::{self, Rotation}};
+use crate::config::config::{Config, LogFormat, LogOutput, LogRotation};
+
+/// Any extra layer that should receive the spans of r_agent, e.g. an OTLP exporter or a test collector
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync + 'static>;
+
+/// Keeps the background log writer alive, buffered logs are flushed when it is dropped
+pub struct LoggingGuard {
+    _writer: Option<WorkerGuard>,
+}
+
+/// Install the global subscriber with the log output configured in `Config`.
+/// Library users that bring their own subscriber simply never call this (or set `log_output: none`).
+pub fn init_logging(cfg: &Config) -> anyhow::Result<LoggingGuard> {
+    init_logging_with_layers(cfg, Vec::new())
+}
+
+/// Same as `init_logging`, `extra_layers` are attached next to the log output.
+/// The log level only filters the log output, extra layers see every span and event.
+pub fn init_logging_with_layers(cfg: &Config, extra_layers: Vec<BoxedLayer>) -> anyhow::Result<LoggingGuard> {
+    let (log_layer, guard) = log_layer(cfg)?.unzip();
+    let mut layers: Vec<BoxedLayer> = log_layer.into_iter().collect();
+    layers.extend(extra_layers);
+    tracing_subscriber::registry()
+        .with(layers)
+        .try_init()
+        .context("Failed to install the tracing subscriber, a global subscriber is already set")?;
+    Ok(LoggingGuard { _writer: guard })
+}
+
+/// The layer writing logs as configured in `Config`, `None` when logging is turned off.
+/// Use it to compose a subscriber yourself, the guard must be kept alive as long as the layer.
+pub fn log_layer(cfg: &Config) -> anyhow::Result<Option<(BoxedLayer, WorkerGuard)>> {
+    let (writer, guard) = match cfg.log_output {
+        LogOutput::None => return Ok(None),
+        LogOutput::Stdout => tracing_appender::non_blocking(std::io::stdout()),
+        LogOutput::File => tracing_appender::non_blocking(rolling::never(&cfg.log_dir, &cfg.log_file)),
+        LogOutput::Rolling => {
+            let rotation = match cfg.log_rotation {
+                LogRotation::Minutely => Rotation::MINUTELY,
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+            };
+            let appender = rolling::Builder::new()
+                                .rotation(rotation)
+                                .filename_prefix(&cfg.log_file)
+                                .build(&cfg.log_dir)
+                                .with_context(|| format!("Failed to create rolling log file in {}", cfg.log_dir))?;
+            tracing_appender::non_blocking(appender)
+        }
+    };
+    let filter = EnvFilter::try_new(&cfg.log_level).with_context(|| format!("Invalid log level: {}", cfg.log_level))?;
+    let layer = match cfg.log_format {
+        LogFormat::Text => fmt::layer().with_writer(writer).with_ansi(false).with_filter(filter).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).with_filter(filter).boxed(),
+    };
+    Ok(Some((layer, guard)))
+}
+
+#[cfg(feature = "otel")]
+pub use otlp::OtlpExporter;
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use anyhow::Context;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+    use super::BoxedLayer;
+
+    /// OTLP/HTTP span exporter, keep it alive for as long as spans should be exported.
+    /// Pending spans are flushed when it is dropped.
+    pub struct OtlpExporter {
+        provider: SdkTracerProvider,
+    }
+
+    impl OtlpExporter {
+        /// `endpoint` is the traces endpoint of the collector, e.g. `http://localhost:4318/v1/traces`
+        pub fn new(endpoint: &str, service_name: &str) -> anyhow::Result<Self> {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                                .with_http()
+                                .with_endpoint(endpoint)
+                                .build()
+                                .with_context(|| format!("Failed to build OTLP exporter for {}", endpoint))?;
+            let provider = SdkTracerProvider::builder()
+                                .with_batch_exporter(exporter)
+                                .with_resource(Resource::builder().with_service_name(service_name.to_string()).build())
+                                .build();
+            Ok(OtlpExporter { provider })
+        }
+
+        /// A layer to pass to `init_logging_with_layers`
+        pub fn layer(&self) -> BoxedLayer {
+            let tracer = self.provider.tracer("r_agent");
+            Box::new(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+    }
+
+    impl Drop for OtlpExporter {
+        fn drop(&mut self) {
+            if let Err(e) = self.provider.shutdown() {
+                eprintln!("Failed to flush OTLP spans: {}", e);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn config(yaml: &str) -> Config {
+        serde_yml::from_str(&format!("{}\nmodels: {{}}\nsummary_model: gpt-4o-mini", yaml)).unwrap()
+    }
+
+    #[test]
+    fn test_log_output_none() {
+        let cfg = config("log_output: none");
+        assert!(log_layer(&cfg).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_json_log_file() {
+        let dir = "./workspace_test/logs_json";
+        let _ = fs::remove_dir_all(dir);
+        let cfg = config(&format!("log_dir: {}\nlog_file: test.log\nlog_format: json\nlog_level: info", dir));
+        let (layer, guard) = log_layer(&cfg).unwrap().unwrap();
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info!(answer = 42, "structured log");
+            tracing::debug!("filtered out");
+        });
+        drop(guard);
+        let content = fs::read_to_string(format!("{}/test.log", dir)).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(record["fields"]["answer"], 42);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
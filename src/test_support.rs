@@ -0,0 +1,152 @@
+//! Fixtures shared by the offline tests
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+use async_trait::async_trait;
+use llm::{FunctionCall, ToolCall};
+use crate::{agent::react_agent::ReactAgent,
+            config::config::Config,
+            memory::base::BaseMemory,
+            model::{base::BaseModel, options::GenerationOptions, schema::{LLMResponse, Message, Usage}},
+            prompt::agent::REACT_END_TOKEN,
+            tool::manager::ToolManager};
+
+pub const MODEL: &str = "gpt-4o-mini";
+
+/// A config whose only model points at the discard port, calls fail fast without leaving the machine
+pub fn offline_config() -> Config {
+    offline_config_with("")
+}
+
+/// `offline_config` with more top level YAML, e.g. a `memory` section
+pub fn offline_config_with(extra: &str) -> Config {
+    serde_yml::from_str(&format!(r#"
+log_output: none
+summary_model: {MODEL}
+models:
+  {MODEL}:
+    api_key: sk-offline
+    base_url: http://127.0.0.1:9/v1/
+{extra}
+"#)).unwrap()
+}
+
+pub fn usage(tokens: u32) -> Usage {
+    Usage { prompt_tokens: tokens, completion_tokens: 0, total_tokens: tokens, cost_usd: 0.0, cache_hit: false }
+}
+
+pub fn text(content: &str) -> LLMResponse {
+    LLMResponse { content: Some(content.to_string()), reasoning_content: None, usage: None, tool_calls: None }
+}
+
+/// A final ReAct answer
+pub fn answer(content: &str) -> LLMResponse {
+    text(&format!("{} {}", content, REACT_END_TOKEN))
+}
+
+pub fn tool_call(id: &str, name: &str, arguments: &str) -> ToolCall {
+    ToolCall {
+        id: id.to_string(),
+        call_type: "function".to_string(),
+        function: FunctionCall { name: name.to_string(), arguments: arguments.to_string() },
+    }
+}
+
+pub fn tool_calls(calls: Vec<ToolCall>) -> LLMResponse {
+    LLMResponse { content: None, reasoning_content: None, usage: None, tool_calls: Some(calls) }
+}
+
+/// Answers every call with `reply(history)`
+pub struct FnModel<F>(pub F);
+
+#[async_trait]
+impl <F: Fn(&[&Message]) -> LLMResponse + Send + Sync> BaseModel for FnModel<F> {
+    async fn call(&self, user_prompt: &Message) -> LLMResponse {
+        self.call_with_history(vec![user_prompt]).await
+    }
+
+    async fn call_with_history(&self, history: Vec<&Message>) -> LLMResponse {
+        (self.0)(&history)
+    }
+
+    fn model_name(&self) -> &str {
+        MODEL
+    }
+}
+
+/// Answers `echo <last message>` and ends the ReAct loop
+pub fn echo_model() -> FnModel<impl Fn(&[&Message]) -> LLMResponse + Send + Sync> {
+    FnModel(|history: &[&Message]| answer(&format!("echo {}", history.last().unwrap().content)))
+}
+
+/// The messages and options of every call, in order
+pub type RequestLog = Arc<Mutex<Vec<(Vec<Message>, GenerationOptions)>>>;
+
+/// Answers with `replies` in order, panics when they run out
+pub struct ScriptedModel {
+    replies: Mutex<VecDeque<String>>,
+    requests: RequestLog,
+    system_prompt: String,
+    json_schema: bool,
+}
+
+impl ScriptedModel {
+    pub fn new<S: Into<String>>(replies: impl IntoIterator<Item = S>) -> Self {
+        ScriptedModel {
+            replies: Mutex::new(replies.into_iter().map(Into::into).collect()),
+            requests: RequestLog::default(),
+            system_prompt: String::new(),
+            json_schema: false,
+        }
+    }
+
+    /// Report `system_prompt`, e.g. the one of the model it stands in for
+    pub fn with_system_prompt(mut self, system_prompt: &str) -> Self {
+        self.system_prompt = system_prompt.to_string();
+        self
+    }
+
+    /// Claim support for JSON schema response formats
+    pub fn with_json_schema(mut self) -> Self {
+        self.json_schema = true;
+        self
+    }
+
+    /// The calls answered so far, the log stays readable after the model moved into an agent
+    pub fn requests(&self) -> RequestLog {
+        self.requests.clone()
+    }
+}
+
+#[async_trait]
+impl BaseModel for ScriptedModel {
+    async fn call(&self, user_prompt: &Message) -> LLMResponse {
+        self.call_with_history(vec![user_prompt]).await
+    }
+
+    async fn call_with_history(&self, history: Vec<&Message>) -> LLMResponse {
+        self.call_with_options(history, &GenerationOptions::default()).await
+    }
+
+    async fn call_with_options(&self, history: Vec<&Message>, options: &GenerationOptions) -> LLMResponse {
+        self.requests.lock().unwrap().push((history.into_iter().cloned().collect(), options.clone()));
+        let reply = self.replies.lock().unwrap().pop_front().expect("ScriptedModel ran out of replies");
+        text(&reply)
+    }
+
+    fn model_name(&self) -> &str {
+        MODEL
+    }
+
+    fn system_prompt(&self) -> &str {
+        &self.system_prompt
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.json_schema
+    }
+}
+
+/// A `ReactAgent` without tools answering with `model`
+pub fn react_agent<M: BaseMemory + Send, B: BaseModel + 'static>(config: &Config, system_prompt: &str, memory: M, model: B) -> ReactAgent<M> {
+    ReactAgent::new(config, MODEL, system_prompt, 3, ToolManager::new(Vec::new()), memory, Vec::new())
+        .map_model(|_| Box::new(model))
+}
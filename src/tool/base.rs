@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{collections::HashMap, fmt::Debug};
+use crate::model::schema::Usage;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ToolParameters {
@@ -25,6 +26,8 @@ pub trait Tool: Debug + Send{
     fn parameters(&self) -> &Value{ self.load().get("parameters").unwrap_or(&Value::Null) }
     fn init(&mut self);
     fn execute(&self, input: &str) -> String;
+    // usage of model calls made by the tool since the last call, added to the usage of the calling agent's run
+    fn take_usage(&self) -> Option<Usage> { None }
 }
 
 
@@ -26,8 +26,8 @@ impl ToolManager {
         self.tools.keys().cloned().collect()
     }
 
-    pub fn get_tool(&self, name: &str) -> Option<&Box<dyn Tool>> {
-        self.tools.get(name)
+    pub fn get_tool(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools.get(name).map(|tool| tool.as_ref())
     }
 
     pub fn get_schema(&self, names: &Vec<String>) -> Vec<Value> {
@@ -36,7 +36,7 @@ impl ToolManager {
         for name in names {
             if let Some(tool) = self.tools.get(name) {
                 // let tool = tool
-                schemas.push(Self::tool_to_schema(&tool));
+                schemas.push(Self::tool_to_schema(tool.as_ref()));
             }
         }
         schemas
@@ -55,7 +55,7 @@ impl ToolManager {
         self.tools.clear();
     }
 
-    pub fn tool_to_schema(tool: &Box<dyn Tool>) -> Value{
+    pub fn tool_to_schema(tool: &dyn Tool) -> Value{
         let schema = json!(
             {
                 "name": tool.name(),
@@ -0,0 +1,174 @@
+use anyhow::Context;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::{model::schema::{LLMResponse, Usage},
+            trajectory::schema::{TrajectoryEvent, TrajectoryRecord}};
+
+/// Trajectory logger
+/// Writes the events of a single agent run as JSON lines into `<dir>/<run_id>.jsonl`.
+pub struct TrajectoryLogger {
+    run_id: String,
+    path: PathBuf,
+    file: File,
+}
+
+impl TrajectoryLogger {
+    /// Creates `dir` if needed and opens the file of a new run
+    pub fn new(dir: &Path) -> anyhow::Result<Self> {
+        let run_id = uuid::Uuid::new_v4().to_string();
+        if !dir.exists() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create trajectory directory: {:?}", dir))?;
+        }
+        let path = dir.join(format!("{}.jsonl", run_id));
+        let file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .with_context(|| format!("Failed to open trajectory file: {:?}", path))?;
+        Ok(TrajectoryLogger { run_id, path, file })
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one event, failures are logged but never interrupt the run
+    pub fn log(&mut self, event: TrajectoryEvent) {
+        let record = TrajectoryRecord {
+            run_id: self.run_id.clone(),
+            timestamp_ms: now_ms(),
+            event,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!("Failed to serialize trajectory record: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = writeln!(self.file, "{}", line) {
+            tracing::error!("Failed to write trajectory to file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// A trajectory loaded back from disk, for analysis and replay
+#[derive(Debug, Clone, Default)]
+pub struct Trajectory {
+    pub records: Vec<TrajectoryRecord>,
+}
+
+impl Trajectory {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open trajectory file: {:?}", path))?;
+        let mut records = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("Failed to read trajectory file: {:?}", path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str::<TrajectoryRecord>(&line)
+                                .with_context(|| format!("Failed to parse line {} of trajectory file: {:?}", i + 1, path))?;
+            records.push(record);
+        }
+        Ok(Trajectory { records })
+    }
+
+    /// List all trajectory files in a directory, oldest first
+    pub fn list(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).with_context(|| format!("Failed to read trajectory directory: {:?}", dir))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "jsonl") {
+                let modified = fs::metadata(&path)?.modified()?;
+                files.push((modified, path));
+            }
+        }
+        files.sort();
+        Ok(files.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Model responses in the order they were received, useful to replay a run
+    pub fn responses(&self) -> impl Iterator<Item = &LLMResponse> {
+        self.records.iter().filter_map(|record| match &record.event {
+            TrajectoryEvent::ModelCall { response, .. } => Some(response),
+            _ => None,
+        })
+    }
+
+    pub fn final_answer(&self) -> Option<&str> {
+        self.records.iter().rev().find_map(|record| match &record.event {
+            TrajectoryEvent::RunFinished { final_answer, .. } => final_answer.as_deref(),
+            _ => None,
+        })
+    }
+
+    /// Sum of the usage of every model call in the trajectory
+    pub fn total_usage(&self) -> Usage {
+        let mut total = Usage::default();
+        for response in self.responses() {
+            if let Some(usage) = &response.usage {
+                total.accumulate(usage);
+            }
+        }
+        total
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::schema::Message;
+
+    #[test]
+    fn test_trajectory_roundtrip() {
+        let dir = PathBuf::from("./workspace_test/trajectory_roundtrip");
+        let mut logger = TrajectoryLogger::new(&dir).unwrap();
+        logger.log(TrajectoryEvent::RunStarted {
+            agent: "ReactAgent".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            system_prompt: "system".to_string(),
+            user_prompt: "hello".to_string(),
+        });
+        logger.log(TrajectoryEvent::ModelCall {
+            iteration: 0,
+            messages: vec![Message::user("hello")],
+            response: LLMResponse {
+                content: Some("hi".to_string()),
+                reasoning_content: None,
+                usage: Some(Usage { prompt_tokens: 3, completion_tokens: 1, total_tokens: 4, cost_usd: 0.5, cache_hit: false }),
+                tool_calls: None,
+            },
+            latency_ms: 10,
+        });
+        logger.log(TrajectoryEvent::RunFinished {
+            iterations: 1,
+            final_answer: Some("hi".to_string()),
+            usage: Usage::default(),
+            latency_ms: 12,
+        });
+
+        let trajectory = Trajectory::load(logger.path()).unwrap();
+        assert_eq!(trajectory.records.len(), 3);
+        assert!(trajectory.records.iter().all(|r| r.run_id == logger.run_id()));
+        assert_eq!(trajectory.responses().count(), 1);
+        assert_eq!(trajectory.total_usage().total_tokens, 4);
+        assert_eq!(trajectory.final_answer(), Some("hi"));
+        assert!(Trajectory::list(&dir).unwrap().contains(&logger.path().to_path_buf()));
+
+        // a file where the directory should be
+        assert!(TrajectoryLogger::new(&logger.path().join("nested")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use llm::ToolCall;
+use serde::{Deserialize, Serialize};
+use crate::model::schema::{LLMResponse, Message, Usage};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Done,
+    // the executor gave no answer for this step
+    Failed,
+}
+
+/// A step of a `PlanExecuteAgent` plan, as the agent exposes it and the trajectory logs it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub description: String,
+    pub status: StepStatus,
+    pub result: Option<String>,
+}
+
+impl PlanStep {
+    pub(crate) fn pending(description: String) -> Self {
+        PlanStep { description, status: StepStatus::Pending, result: None }
+    }
+}
+
+/// One line of a trajectory file.
+/// Every record carries the run it belongs to and when it was written, the payload is in `event`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrajectoryRecord {
+    pub run_id: String,
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub event: TrajectoryEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TrajectoryEvent {
+    RunStarted {
+        agent: String,
+        model: String,
+        system_prompt: String,
+        user_prompt: String,
+    },
+    ModelCall {
+        iteration: usize,
+        messages: Vec<Message>,
+        response: LLMResponse,
+        latency_ms: u64,
+    },
+    ToolCall {
+        iteration: usize,
+        tool_call: ToolCall,
+        output: String,
+        latency_ms: u64,
+    },
+    // the plan of a `PlanExecuteAgent`
+    PlanCreated {
+        steps: Vec<String>,
+    },
+    StepFinished {
+        index: usize,
+        step: PlanStep,
+    },
+    PlanRevised {
+        plan: Vec<PlanStep>,
+    },
+    RunFinished {
+        iterations: usize,
+        final_answer: Option<String>,
+        usage: Usage,
+        latency_ms: u64,
+    },
+}